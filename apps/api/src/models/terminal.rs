@@ -1,4 +1,4 @@
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
 
 // we're using axum's macros 
 #[derive(Deserialize, Debug)]
@@ -9,7 +9,4 @@ pub struct TerminalBody {
 #[derive(serde::Deserialize, Debug)]
 pub struct ExecResponse {
     pub job_id: String,
-    pub stream_url: Option<String>,
-    pub status_url: Option<String>,
-    pub cancel_url: Option<String>,
 }
\ No newline at end of file
@@ -3,7 +3,6 @@ use axum::{
     http::StatusCode,
     response::IntoResponse,
 };
-use serde::Deserialize;
 use tracing::info;
 
 use crate::services::session_manager::SessionManager;
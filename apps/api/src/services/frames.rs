@@ -0,0 +1,139 @@
+use anyhow::Result;
+use futures_util::stream::{self, Stream, StreamExt};
+use reqwest::header::CONTENT_TYPE;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// What we ask nodes for on stream endpoints: length-prefixed MessagePack.
+pub const ACCEPT: &str = "application/msgpack";
+
+/// One StreamFrame from a node's job or session stream.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Frame {
+    pub t: String,
+    #[serde(default)]
+    pub seq: u64,
+    pub d: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enc: Option<String>,
+}
+
+/// Largest MessagePack item accepted; a bigger length prefix means the
+/// stream is out of step.
+const MAX_FRAME: usize = 16 << 20;
+
+/// Decodes a stream response. Nodes that answer with ndjson instead of
+/// MessagePack (older builds) are read line by line.
+pub fn decode(resp: reqwest::Response) -> impl Stream<Item = Result<Frame>> {
+    let binary = resp
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/msgpack"));
+    decode_body(resp.bytes_stream(), binary)
+}
+
+/// Items that don't decode are logged and skipped; the stream only fails on
+/// transport errors and on binary framing it can't recover from.
+fn decode_body<B: AsRef<[u8]>>(
+    body: impl Stream<Item = reqwest::Result<B>> + Send + 'static,
+    binary: bool,
+) -> impl Stream<Item = Result<Frame>> {
+    let body = body.boxed();
+    stream::unfold(Some((body, Vec::new())), move |state| async move {
+        let (mut body, mut buf) = state?;
+        loop {
+            match next_frame(&buf, binary) {
+                Some(Ok((frame, used))) => {
+                    buf.drain(..used);
+                    match frame {
+                        Some(frame) => return Some((Ok(frame), Some((body, buf)))),
+                        None => continue,
+                    }
+                }
+                Some(Err(e)) => return Some((Err(e), None)),
+                None => {}
+            }
+            match body.next().await {
+                Some(Ok(chunk)) => buf.extend_from_slice(chunk.as_ref()),
+                Some(Err(e)) => return Some((Err(e.into()), None)),
+                None if binary && !buf.is_empty() => {
+                    return Some((Err(anyhow::anyhow!("stream ended inside a {}-byte frame", buf.len())), None));
+                }
+                None => return None,
+            }
+        }
+    })
+}
+
+/// The first complete item in `buf` and how many bytes it took; the frame
+/// is None if the item didn't decode. Err if the length prefix is absurd.
+fn next_frame(buf: &[u8], binary: bool) -> Option<Result<(Option<Frame>, usize)>> {
+    let (item, used) = if binary {
+        let len = u32::from_be_bytes(buf.get(..4)?.try_into().ok()?) as usize;
+        if len > MAX_FRAME {
+            return Some(Err(anyhow::anyhow!("frame length {len} exceeds {MAX_FRAME}")));
+        }
+        (rmp_serde::from_slice(buf.get(4..4 + len)?).map_err(anyhow::Error::from), 4 + len)
+    } else {
+        let end = buf.iter().position(|&b| b == b'\n')?;
+        (serde_json::from_slice(&buf[..end]).map_err(anyhow::Error::from), end + 1)
+    };
+    match item {
+        Ok(frame) => Some(Ok((Some(frame), used))),
+        Err(e) => {
+            warn!("skipping undecodable stream item ({used} bytes): {e}");
+            Some(Ok((None, used)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collect(chunks: Vec<&[u8]>, binary: bool) -> Vec<Result<Frame>> {
+        let body = stream::iter(chunks.into_iter().map(|c| Ok::<_, reqwest::Error>(c.to_vec())).collect::<Vec<_>>());
+        let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        rt.block_on(decode_body(body, binary).collect())
+    }
+
+    fn msgpack(f: &Frame) -> Vec<u8> {
+        let payload = rmp_serde::to_vec_named(f).unwrap();
+        let mut out = (payload.len() as u32).to_be_bytes().to_vec();
+        out.extend(payload);
+        out
+    }
+
+    #[test]
+    fn skips_a_bad_ndjson_line() {
+        let body = b"{\"t\":\"stdout\",\"seq\":1,\"d\":\"a\"}\n{\"t\":\"std\n{\"t\":\"event\",\"seq\":2,\"d\":\"exit:Some(0)\"}\n";
+        // split mid-line too
+        let frames = collect(vec![&body[..20], &body[20..]], false);
+        let frames: Vec<Frame> = frames.into_iter().map(|f| f.unwrap()).collect();
+        assert_eq!(frames.len(), 2);
+        assert_eq!((frames[0].seq, frames[0].d.as_str()), (1, "a"));
+        assert_eq!((frames[1].seq, frames[1].d.as_str()), (2, "exit:Some(0)"));
+    }
+
+    #[test]
+    fn skips_a_bad_msgpack_item_but_not_bad_framing() {
+        let good = |seq| msgpack(&Frame { t: "stdout".into(), seq, d: "x".into(), enc: None });
+        let mut body = good(1);
+        body.extend([0, 0, 0, 1, 0xc1]); // 0xc1 is never valid
+        body.extend(good(2));
+        let frames = collect(vec![&body], true);
+        assert_eq!(frames.iter().map(|f| f.as_ref().unwrap().seq).collect::<Vec<_>>(), [1, 2]);
+
+        let mut body = good(1);
+        body.extend([0xff, 0xff, 0xff, 0xff]);
+        body.extend(good(2));
+        let frames = collect(vec![&body], true);
+        assert_eq!(frames.len(), 2);
+        assert!(frames[0].is_ok() && frames[1].is_err());
+
+        let body = good(1);
+        let frames = collect(vec![&body[..body.len() - 1]], true);
+        assert!(frames.len() == 1 && frames[0].is_err());
+    }
+}
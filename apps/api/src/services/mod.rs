@@ -1,2 +1,4 @@
+pub mod frames;
 pub mod node_client;
-pub mod session_manager;
\ No newline at end of file
+pub mod session_manager;
+pub mod signing;
\ No newline at end of file
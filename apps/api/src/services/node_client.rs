@@ -1,13 +1,13 @@
-use anyhow::{Result, Context};
-use futures_util::StreamExt;
+use anyhow::Result;
 use regex::Regex;
-use reqwest::{Client, Response};
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use reqwest::{Client, Method, RequestBuilder};
 use std::sync::Arc;
-use serde::Deserialize;
-use serde_json::Value;
 use tokio::sync::RwLock;
 use tracing::{debug, info};
 use crate::models::terminal::ExecResponse;
+use crate::services::frames;
+use crate::services::signing::SigningKey;
 
 // UPDATE
 #[derive(Clone)]
@@ -15,6 +15,7 @@ pub struct NodeClient {
     pub http: Client,
     pub ansi_re: Regex,
     pub cache: Arc<RwLock<std::collections::HashMap<String, NodeState>>>,
+    pub key: Option<SigningKey>,
 }
 
 #[derive(Clone, Default)]
@@ -26,6 +27,10 @@ pub struct NodeState {
 
 impl NodeClient {
     pub fn new() -> Self {
+        let key = SigningKey::from_env();
+        if key.is_none() {
+            eprintln!("⚠️  Warning: NODE_AUTH_KEY_ID / NODE_AUTH_SECRET not set, node requests are unsigned");
+        }
         Self {
             http: Client::builder()
                 // .timeout(None)
@@ -33,21 +38,36 @@ impl NodeClient {
                 .expect("reqwest client build failed"),
             ansi_re: Regex::new(r"\x1B\[[0-9;]*[a-zA-Z]").unwrap(),
             cache: Arc::new(RwLock::new(std::collections::HashMap::new())),
+            key,
+        }
+    }
+
+    /// Every request to a node goes through here so it gets signed.
+    fn request(&self, method: Method, url: &str, body: Option<&serde_json::Value>) -> Result<RequestBuilder> {
+        let url = reqwest::Url::parse(url)?;
+        let body = match body {
+            Some(v) => serde_json::to_vec(v)?,
+            None => Vec::new(),
+        };
+        let mut req = self.http.request(method.clone(), url.clone());
+        if let Some(key) = &self.key {
+            let path = match url.query() {
+                Some(q) => format!("{}?{q}", url.path()),
+                None => url.path().to_string(),
+            };
+            req = req.headers(key.headers(method.as_str(), &path, &body));
         }
+        if !body.is_empty() {
+            req = req.header(CONTENT_TYPE, "application/json").body(body);
+        }
+        Ok(req)
     }
 
     /// Send command to PTY and wait up to ~10s for output
     pub async fn post_exec(&self, node_url: &str, token: &str, cmd: &str) -> Result<String> {
-        use serde::Deserialize;
         use tokio::time::{timeout, Duration};
         use futures_util::StreamExt;
 
-        #[derive(Deserialize)]
-        struct Frame {
-            t: Option<String>,
-            d: Option<String>,
-        }
-
         {
             let mut cache = self.cache.write().await;
             let state = cache.entry(token.to_string()).or_default();
@@ -57,7 +77,7 @@ impl NodeClient {
                 if let Some(prev_job) = &state.last_job_id {
                     let close_url = format!("{}/stream/{}/close", node_url.trim_end_matches('/'), prev_job);
                     info!("Attempting to kill running job {}", close_url);
-                    match self.http.post(&close_url).send().await {
+                    match self.request(Method::POST, &close_url, None)?.send().await {
                         Ok(resp) if resp.status().is_success() => {
                             info!("Closed previous session {} successfully", prev_job);
                         }
@@ -76,7 +96,7 @@ impl NodeClient {
         debug!("POST to PTY node {}", url);
 
         let body = serde_json::json!({ "cmd": [cmd] });
-        let resp = self.http.post(&url).json(&body).send().await?;
+        let resp = self.request(Method::POST, &url, Some(&body))?.send().await?;
         if !resp.status().is_success() {
             let status = resp.status();
             let text = resp.text().await.unwrap_or_default();
@@ -103,58 +123,40 @@ impl NodeClient {
         let mut exit_str = String::from("still running...");
 
         let stream_url = format!("{}/stream/{}?from=0", node_url.trim_end_matches('/'), job_id);
-        let resp = self.http.get(&stream_url).send().await?;
-        let mut stream = resp.bytes_stream();
+        let resp = self.request(Method::GET, &stream_url, None)?.header(ACCEPT, frames::ACCEPT).send().await?;
+        let mut stream = std::pin::pin!(frames::decode(resp));
 
         let _ = timeout(Duration::from_secs(10), async {
-            while let Some(chunk) = stream.next().await {
-                match chunk {
-                    Ok(bytes) => {
-                        for line in String::from_utf8_lossy(&bytes).lines() {
-                            if line.trim().is_empty() {
-                                continue;
-                            }
-
-                            // append to cache backlog so /terminal can read it
-                            {
-                                let mut cache = self.cache.write().await;
-                                let state = cache.entry(token.to_string()).or_default();
-                                state.backlog.push_str(line);
-                                state.backlog.push('\n');
-                            }
-
-                            if let Ok(frame) = serde_json::from_str::<Frame>(line) {
-                                match frame.t.as_deref() {
-                                    Some("stdout") => {
-                                        if let Some(d) = frame.d { stdout_buf.push_str(&d); }
-                                    }
-                                    Some("stderr") => {
-                                        if let Some(d) = frame.d { stderr_buf.push_str(&d); }
-                                    }
-                                    Some("event") => {
-                                        if let Some(d) = frame.d {
-                                            if d.starts_with("exit:") {
-                                                exit_str = d.trim_start_matches("exit:").to_string();
-                                                // mark stopped
-                                                let mut cache = self.cache.write().await;
-                                                if let Some(state) = cache.get_mut(token) {
-                                                    state.running = false;
-                                                }
-                                                return; // end early
-                                            }
-                                        }
-                                    }
-                                    _ => {}
-                                }
-                            } else {
-                                debug!("non-JSON stream fragment: {}", line);
-                            }
-                        }
-                    }
+            while let Some(frame) = stream.next().await {
+                let frame = match frame {
+                    Ok(frame) => frame,
                     Err(e) => {
                         debug!("stream read error: {}", e);
                         break;
                     }
+                };
+
+                // append to cache backlog so /terminal can read it
+                {
+                    let mut cache = self.cache.write().await;
+                    let state = cache.entry(token.to_string()).or_default();
+                    state.backlog.push_str(&serde_json::to_string(&frame).unwrap_or_default());
+                    state.backlog.push('\n');
+                }
+
+                match frame.t.as_str() {
+                    "stdout" => stdout_buf.push_str(&frame.d),
+                    "stderr" => stderr_buf.push_str(&frame.d),
+                    "event" if frame.d.starts_with("exit:") => {
+                        exit_str = frame.d.trim_start_matches("exit:").to_string();
+                        // mark stopped
+                        let mut cache = self.cache.write().await;
+                        if let Some(state) = cache.get_mut(token) {
+                            state.running = false;
+                        }
+                        return; // end early
+                    }
+                    _ => {}
                 }
             }
 
@@ -205,9 +207,7 @@ impl NodeClient {
         use futures_util::StreamExt;
 
         #[derive(Deserialize)]
-        struct Frame { t: Option<String>, d: Option<String> }
-        #[derive(Deserialize)]
-        struct Status { state: String, exit_code: Option<i32>, seq_latest: u64 }
+        struct Status { seq_latest: u64 }
 
         // ---- resolve job ----
         let last_job_id = {
@@ -220,15 +220,15 @@ impl NodeClient {
 
         // ---- query latest status ----
         let status_url = format!("{}/status/{}", node_url.trim_end_matches('/'), last_job_id);
-        let status: Status = self.http.get(&status_url).send().await?
+        let status: Status = self.request(Method::GET, &status_url, None)?.send().await?
             .error_for_status()?
             .json().await?;
 
         // ---- open stream near the end ----
         let from = status.seq_latest.saturating_sub(800);
         let stream_url = format!("{}/stream/{}?from={}", node_url.trim_end_matches('/'), last_job_id, from);
-        let resp = self.http.get(&stream_url).send().await?;
-        let mut stream = resp.bytes_stream();
+        let resp = self.request(Method::GET, &stream_url, None)?.header(ACCEPT, frames::ACCEPT).send().await?;
+        let mut stream = std::pin::pin!(frames::decode(resp));
 
         // ---- collect up to 10s or until exit ----
         let mut stdout_buf = String::new();
@@ -236,33 +236,24 @@ impl NodeClient {
         let mut exit_str = String::from("still running...");
 
         let _ = timeout(Duration::from_secs(10), async {
-            while let Some(chunk) = stream.next().await {
-                match chunk {
-                    Ok(bytes) => {
-                        for line in String::from_utf8_lossy(&bytes).lines() {
-                            if line.trim().is_empty() { continue; }
-
-                            if let Ok(frame) = serde_json::from_str::<Frame>(line) {
-                                match frame.t.as_deref() {
-                                    Some("stdout") => if let Some(d) = frame.d { stdout_buf.push_str(&d); },
-                                    Some("stderr") => if let Some(d) = frame.d { stderr_buf.push_str(&d); },
-                                    Some("event") => if let Some(d) = frame.d {
-                                        if d.starts_with("exit:") {
-                                            exit_str = d.trim_start_matches("exit:").to_string();
-                                            let mut cache = self.cache.write().await;
-                                            if let Some(s) = cache.get_mut(token) { s.running = false; }
-                                            return; // exit early
-                                        }
-                                    },
-                                    _ => {}
-                                }
-                            }
-                        }
-                    }
+            while let Some(frame) = stream.next().await {
+                let frame = match frame {
+                    Ok(frame) => frame,
                     Err(e) => {
                         debug!("stream read error: {}", e);
                         break;
                     }
+                };
+                match frame.t.as_str() {
+                    "stdout" => stdout_buf.push_str(&frame.d),
+                    "stderr" => stderr_buf.push_str(&frame.d),
+                    "event" if frame.d.starts_with("exit:") => {
+                        exit_str = frame.d.trim_start_matches("exit:").to_string();
+                        let mut cache = self.cache.write().await;
+                        if let Some(s) = cache.get_mut(token) { s.running = false; }
+                        return; // exit early
+                    }
+                    _ => {}
                 }
             }
             // timeout hit → mark not running
@@ -271,8 +262,7 @@ impl NodeClient {
         }).await;
 
         // ---- clean + tail ----
-        let ansi = Regex::new(r"\x1B\[[0-9;]*[A-Za-z]").unwrap();
-        let cleaned = ansi.replace_all(&format!("{}{}", stdout_buf, stderr_buf), "").to_string();
+        let cleaned = self.ansi_re.replace_all(&format!("{}{}", stdout_buf, stderr_buf), "").to_string();
         let lines: Vec<&str> = cleaned.lines().collect();
         let tail = lines[lines.len().saturating_sub(50)..].join("\n");
 
@@ -292,7 +282,7 @@ impl NodeClient {
     
     pub async fn get_file(&self, node_url: &str, path: &str) -> Result<String> {
         let url = format!("{}/sandbox/{}", node_url.trim_end_matches('/'), path);
-        let resp = self.http.get(&url).send().await?;
+        let resp = self.request(Method::GET, &url, None)?.send().await?;
         if resp.status().is_success() {
             Ok(resp.text().await?)
         } else {
@@ -305,7 +295,7 @@ impl NodeClient {
     pub async fn write_file(&self, node_url: &str, path: &str, content: &str) -> Result<String> {
         let url = format!("{}/sandbox/{}", node_url.trim_end_matches('/'), path);
         let body = serde_json::json!({ "content": content });
-        let resp = self.http.post(&url).json(&body).send().await?;
+        let resp = self.request(Method::POST, &url, Some(&body))?.send().await?;
         if resp.status().is_success() {
             Ok(resp.text().await?)
         } else {
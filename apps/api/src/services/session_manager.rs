@@ -22,8 +22,7 @@ impl SessionManager {
             .and_then(|txt| serde_json::from_str(&txt).ok())
             .unwrap_or_else(|| {
                 eprintln!("⚠️  Warning: could not read {}", path);
-                let m = HashMap::new();
-                m
+                HashMap::new()
             });
 
         let client = NodeClient::new();
@@ -0,0 +1,44 @@
+use hmac::{Hmac, Mac};
+use reqwest::header::{HeaderMap, HeaderValue};
+use sha2::{Digest, Sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Key used to sign requests to isolated-exec nodes. The node must list the
+/// same id and secret in its keys file.
+#[derive(Clone)]
+pub struct SigningKey {
+    id: String,
+    secret: Vec<u8>,
+}
+
+impl SigningKey {
+    /// Reads NODE_AUTH_KEY_ID and NODE_AUTH_SECRET.
+    pub fn from_env() -> Option<Self> {
+        let id = std::env::var("NODE_AUTH_KEY_ID").ok()?;
+        let secret = std::env::var("NODE_AUTH_SECRET").ok()?;
+        Some(Self { id, secret: secret.into_bytes() })
+    }
+
+    /// Headers for one request; `path` includes the query string.
+    pub fn headers(&self, method: &str, path: &str, body: &[u8]) -> HeaderMap {
+        let ts = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+        let nonce = hex::encode(rand::random::<[u8; 16]>());
+        let canonical = format!("{method}\n{path}\n{ts}\n{nonce}\n{}", hex::encode(Sha256::digest(body)));
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).expect("hmac accepts any key length");
+        mac.update(canonical.as_bytes());
+        let sig = hex::encode(mac.finalize().into_bytes());
+
+        let mut h = HeaderMap::new();
+        let mut put = |name: &'static str, v: String| {
+            if let Ok(v) = HeaderValue::from_str(&v) {
+                h.insert(name, v);
+            }
+        };
+        put("x-node-key-id", self.id.clone());
+        put("x-node-timestamp", ts.to_string());
+        put("x-node-nonce", nonce);
+        put("x-node-signature", sig);
+        h
+    }
+}
@@ -0,0 +1,138 @@
+use crate::config::AuthConfig;
+use crate::state::AppState;
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::Response;
+use hmac::{Hmac, Mac};
+use parking_lot::{Mutex, RwLock};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub const KEY_ID: &str = "x-node-key-id";
+pub const TIMESTAMP: &str = "x-node-timestamp";
+pub const NONCE: &str = "x-node-nonce";
+pub const SIGNATURE: &str = "x-node-signature";
+
+/// Largest request body that will be buffered for verification.
+const MAX_BODY: usize = 32 << 20;
+
+/// Verifies HMAC-SHA256 request signatures from agent-api. Several keys may
+/// be active at once so a new one can be rolled out before the old one is
+/// removed; the keys file is re-read on SIGHUP.
+pub struct NodeAuth {
+    enable: bool,
+    keys_file: PathBuf,
+    max_skew: Duration,
+    keys: RwLock<HashMap<String, Vec<u8>>>,
+    nonces: Mutex<HashMap<String, Instant>>,
+}
+
+impl NodeAuth {
+    pub fn new(cfg: &AuthConfig) -> Self {
+        let auth = Self {
+            enable: cfg.enable,
+            keys_file: cfg.keys_file.clone(),
+            max_skew: Duration::from_millis(cfg.max_skew_ms),
+            keys: RwLock::new(HashMap::new()),
+            nonces: Mutex::new(HashMap::new()),
+        };
+        if auth.enable {
+            auth.reload();
+        } else {
+            tracing::warn!("request authentication disabled");
+        }
+        auth
+    }
+
+    /// Re-reads the keys file (`key_id = "secret"` per line). On error the
+    /// current keys stay in place.
+    pub fn reload(&self) {
+        let parsed = std::fs::read_to_string(&self.keys_file)
+            .map_err(anyhow::Error::from)
+            .and_then(|text| Ok(toml::from_str::<HashMap<String, String>>(&text)?));
+        match parsed {
+            Ok(keys) => {
+                let ids: Vec<_> = keys.keys().cloned().collect();
+                *self.keys.write() = keys.into_iter().map(|(id, s)| (id, s.into_bytes())).collect();
+                tracing::info!("auth: loaded keys {ids:?} from {}", self.keys_file.display());
+            }
+            Err(e) => {
+                tracing::warn!("auth: could not load {}: {e}", self.keys_file.display());
+            }
+        }
+    }
+
+    fn verify(&self, method: &str, path: &str, headers: &axum::http::HeaderMap, body: &[u8]) -> Result<(), &'static str> {
+        let get = |name| headers.get(name).and_then(|v| v.to_str().ok());
+        let (Some(key_id), Some(ts), Some(nonce), Some(sig)) = (get(KEY_ID), get(TIMESTAMP), get(NONCE), get(SIGNATURE)) else {
+            return Err("missing signature headers");
+        };
+        let ts: u64 = ts.parse().map_err(|_| "bad timestamp")?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+        if now.abs_diff(ts) > self.max_skew.as_millis() as u64 {
+            return Err("stale timestamp");
+        }
+        let sig = hex::decode(sig).map_err(|_| "bad signature")?;
+        {
+            let keys = self.keys.read();
+            let key = keys.get(key_id).ok_or("unknown key id")?;
+            let mut mac = Hmac::<Sha256>::new_from_slice(key).map_err(|_| "bad key")?;
+            mac.update(canonical(method, path, ts, nonce, body).as_bytes());
+            mac.verify_slice(&sig).map_err(|_| "bad signature")?;
+        }
+        // only remember nonces of valid requests, for as long as their timestamp is acceptable
+        let mut nonces = self.nonces.lock();
+        if nonces.len() > 4096 {
+            let window = self.max_skew * 2;
+            nonces.retain(|_, seen| seen.elapsed() < window);
+        }
+        if nonces.insert(format!("{key_id}:{nonce}"), Instant::now()).is_some() {
+            return Err("replayed nonce");
+        }
+        Ok(())
+    }
+}
+
+/// The string both sides sign: method, path with query, timestamp (unix ms),
+/// nonce and the hex SHA-256 of the body, newline-separated.
+pub fn canonical(method: &str, path: &str, ts: u64, nonce: &str, body: &[u8]) -> String {
+    format!("{method}\n{path}\n{ts}\n{nonce}\n{}", hex::encode(Sha256::digest(body)))
+}
+
+/// Middleware: everything except /health needs a valid signature.
+pub async fn require_signature(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Result<Response, (StatusCode, String)> {
+    let auth = &state.auth;
+    if !auth.enable || req.uri().path() == "/health" {
+        return Ok(next.run(req).await);
+    }
+    let (parts, body) = req.into_parts();
+    let body = axum::body::to_bytes(body, MAX_BODY)
+        .await
+        .map_err(|e| (StatusCode::PAYLOAD_TOO_LARGE, e.to_string()))?;
+    let path = parts.uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+    if let Err(reason) = auth.verify(parts.method.as_str(), path, &parts.headers, &body) {
+        tracing::info!("auth: rejected {} {path}: {reason}", parts.method);
+        return Err((StatusCode::UNAUTHORIZED, reason.into()));
+    }
+    Ok(next.run(Request::from_parts(parts, Body::from(body))).await)
+}
+
+/// Reloads the keys file whenever the server gets SIGHUP.
+pub fn spawn_reload(state: AppState) -> anyhow::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut hup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hup.recv().await.is_some() {
+            state.auth.reload();
+        }
+    });
+    Ok(())
+}
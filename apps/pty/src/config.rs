@@ -1,24 +1,343 @@
-use std::path::Path;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
-#[derive(Clone, Debug)]
+const CONFIG_ENV: &str = "SANDBOX_CONFIG";
+const CONFIG_PATHS: &[&str] = &["config/sandbox.toml", "/app/config/sandbox.toml"];
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct SandboxConfig {
+    pub namespaces: NamespaceConfig,
+    pub limits: Limits,
+    pub cgroup: CgroupConfig,
+    pub seccomp: SeccompConfig,
+    pub timeouts: TimeoutConfig,
+    pub files: FilesConfig,
+    pub auth: AuthConfig,
+    pub log: LogConfig,
+    pub profiles: BTreeMap<String, ProfileConfig>,
+}
+
+impl SandboxConfig {
+    /// Loads `$SANDBOX_CONFIG`, or the first of `CONFIG_PATHS` that exists.
+    /// Falls back to built-in defaults when no file is found.
+    pub fn load() -> anyhow::Result<Self> {
+        let path = std::env::var_os(CONFIG_ENV)
+            .map(PathBuf::from)
+            .or_else(|| CONFIG_PATHS.iter().map(PathBuf::from).find(|p| p.exists()));
+        match path {
+            Some(p) => Self::from_file(&p),
+            None => {
+                tracing::warn!("no sandbox config found, using defaults");
+                Ok(Self::default())
+            }
+        }
+    }
+
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("read {}: {e}", path.display()))?;
+        let cfg = Self::parse(&text)
+            .map_err(|e| anyhow::anyhow!("parse {}: {e}", path.display()))?;
+        tracing::info!("loaded sandbox config from {}", path.display());
+        Ok(cfg)
+    }
+
+    fn parse(text: &str) -> anyhow::Result<Self> {
+        let cfg: Self = toml::from_str(text)?;
+        for (name, p) in &cfg.profiles {
+            if p.program.trim().is_empty() {
+                anyhow::bail!("profile {name:?} has no program");
+            }
+        }
+        Ok(cfg)
+    }
+}
+
+/// Which namespaces an isolated job or session gets. `enable` is the default
+/// when a request doesn't set `isolate` itself.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct NamespaceConfig {
+    pub enable: bool,
+    pub pid: bool,
+    pub mount: bool,
+    pub user: bool,
+    pub net: bool,
+    /// host directory bind-mounted at /sandbox inside the mount namespace
+    pub sandbox: PathBuf,
+}
+
+impl Default for NamespaceConfig {
+    fn default() -> Self {
+        Self { enable: false, pid: true, mount: true, user: true, net: false, sandbox: "/sandbox".into() }
+    }
+}
+
+/// Per-child resource ceilings, applied as rlimits before exec and mirrored
+/// into the child's cgroup when cgroups are enabled. A value of 0 means unlimited.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Limits {
+    pub cpu_ms: u64,
+    pub mem_mb: u64,
+    pub fsize_mb: u64,
+    pub nproc: u64,
+    pub nofile: u64,
+    /// cgroup-only: CPU bandwidth as a percentage of one core
+    pub cpu_pct: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self { cpu_ms: 300_000, mem_mb: 512, fsize_mb: 128, nproc: 256, nofile: 4096, cpu_pct: 100 }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct CgroupConfig {
+    pub enable: bool,
+    pub root: PathBuf,
+}
+
+impl Default for CgroupConfig {
+    fn default() -> Self {
+        Self { enable: true, root: "/sys/fs/cgroup/isolated-exec".into() }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct FilesConfig {
+    /// host directory served by the /sandbox file routes
+    pub root: PathBuf,
+}
+
+impl Default for FilesConfig {
+    fn default() -> Self {
+        Self { root: "/sandbox".into() }
+    }
+}
+
+/// Request signing between agent-api and this node; see `auth.rs`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    pub enable: bool,
+    /// TOML table of `key_id = "secret"`, re-read on SIGHUP
+    pub keys_file: PathBuf,
+    pub max_skew_ms: u64,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self { enable: true, keys_file: "/run/secrets/isolated-exec-keys.toml".into(), max_skew_ms: 30_000 }
+    }
+}
+
+/// On-disk frame logs backing `?from=` replay beyond the memory backlog.
+/// Sizes in MiB; 0 means unlimited.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    pub enable: bool,
+    pub dir: PathBuf,
+    pub max_file_mb: u64,
+    /// finished logs are deleted oldest-first to stay under this
+    pub max_total_mb: u64,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self { enable: true, dir: "/var/lib/isolated-exec/log".into(), max_file_mb: 64, max_total_mb: 1024 }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct SeccompConfig {
+    /// profile used when a request doesn't name one
+    pub profile: String,
+}
+
+impl Default for SeccompConfig {
+    fn default() -> Self {
+        Self { profile: "default".into() }
+    }
+}
+
+/// Deadlines and retention in milliseconds; 0 disables the corresponding one.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct TimeoutConfig {
+    pub job_ms: u64,
+    pub grace_ms: u64,
+    /// sessions with no input or output for this long are hung up
+    pub idle_session_ms: u64,
+    /// exited jobs and sessions are dropped from memory after this long
+    pub retention_ms: u64,
+    pub reap_interval_ms: u64,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        Self {
+            job_ms: 600_000,
+            grace_ms: 5_000,
+            idle_session_ms: 1_200_000,
+            retention_ms: 600_000,
+            reap_interval_ms: 30_000,
+        }
+    }
+}
+
+/// Per-request limits; each field may only lower the configured ceiling.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct LimitOverrides {
+    pub cpu_ms: Option<u64>,
+    pub mem_mb: Option<u64>,
+    pub fsize_mb: Option<u64>,
+    pub nproc: Option<u64>,
+    pub nofile: Option<u64>,
+    pub cpu_pct: Option<u64>,
+}
+
+impl Limits {
+    pub fn clamp(&self, req: Option<&LimitOverrides>) -> Limits {
+        let Some(req) = req else { return *self };
+        let pick = |ceiling: u64, want: Option<u64>| match (ceiling, want) {
+            (c, Some(w)) if w > 0 && (c == 0 || w < c) => w,
+            (c, _) => c,
+        };
+        Limits {
+            cpu_ms: pick(self.cpu_ms, req.cpu_ms),
+            mem_mb: pick(self.mem_mb, req.mem_mb),
+            fsize_mb: pick(self.fsize_mb, req.fsize_mb),
+            nproc: pick(self.nproc, req.nproc),
+            nofile: pick(self.nofile, req.nofile),
+            cpu_pct: pick(self.cpu_pct, req.cpu_pct),
+        }
+    }
+}
+
+/// A session profile from `[profiles.<name>]`, on top of the built-in
+/// default, bash, posix, zsh and busybox (which a table of the same name replaces).
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ProfileConfig {
+    pub program: String,
+    pub args: Vec<String>,
+    /// added to the server's environment
+    pub env: BTreeMap<String, String>,
+    pub cwd: Option<PathBuf>,
+    /// typed into the terminal once the program has started
+    pub init: Option<String>,
+    pub term: Option<String>,
+    /// sets LANG and LC_ALL
+    pub locale: Option<String>,
+    /// bash or zsh programs only: OSC 133 hooks, so /run works
+    pub integration: bool,
+}
+
+impl ProfileConfig {
+    fn builtin(program: &str, args: &[&str], integration: bool) -> Self {
+        ProfileConfig { program: program.into(), args: args.iter().map(|a| a.to_string()).collect(), integration, ..Default::default() }
+    }
+}
+
+fn builtin_profiles() -> BTreeMap<String, ProfileConfig> {
+    let posix = ProfileConfig::builtin("/bin/sh", &["-i"], false);
+    let bash = if Path::new("/bin/bash").exists() { ProfileConfig::builtin("/bin/bash", &["-li"], true) } else { posix.clone() };
+    BTreeMap::from([
+        ("default".to_string(), bash.clone()),
+        ("bash".to_string(), bash),
+        ("posix".to_string(), posix),
+        ("zsh".to_string(), ProfileConfig::builtin("/bin/zsh", &["-li"], true)),
+        ("busybox".to_string(), ProfileConfig::builtin("/bin/busybox", &["sh", "-i"], false)),
+    ])
+}
+
+/// What a session is started with, after defaults and shell integration.
+#[derive(Debug)]
 pub struct ShellProfile {
+    pub name: String,
     pub program: String,
     pub args: Vec<String>,
+    pub env: Vec<(String, String)>,
+    pub cwd: Option<PathBuf>,
+    /// the request's working directory, opened beneath the sandbox root; wins over `cwd`
+    pub dir: Option<std::os::fd::OwnedFd>,
+    pub user: Option<RunAs>,
+    pub init: Option<String>,
+    /// prints OSC 133 command markers (see `executor::integration`)
+    pub markers: bool,
 }
 
-pub fn resolve_profile(name: Option<&str>) -> ShellProfile {
-    let name = name.unwrap_or("default").to_ascii_lowercase();
-    match name.as_str() {
-        "posix" => ShellProfile { program: "/bin/sh".into(), args: vec!["-i".into()] },
-        "zsh" => ShellProfile { program: "/bin/zsh".into(), args: vec!["-li".into()] },
-        "busybox" => ShellProfile { program: "/bin/busybox".into(), args: vec!["sh".into(), "-i".into()] },
-        _ => {
-            let bash = "/bin/bash";
-            if Path::new(bash).exists() {
-                ShellProfile { program: bash.into(), args: vec!["-li".into()] }
-            } else {
-                ShellProfile { program: "/bin/sh".into(), args: vec!["-i".into()] }
+/// Credentials the session's program runs with; its only group is `gid`.
+#[derive(Clone, Copy, Debug)]
+pub struct RunAs {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl SandboxConfig {
+    /// Built-in profiles overlaid with the configured ones, by lowercase name.
+    pub fn profiles(&self) -> BTreeMap<String, ProfileConfig> {
+        let mut all = builtin_profiles();
+        all.extend(self.profiles.iter().map(|(name, p)| (name.to_ascii_lowercase(), p.clone())));
+        all
+    }
+
+    /// The named profile, "default" if none; None if there is no such profile.
+    /// bash with integration runs as a non-login shell, since login shells
+    /// ignore --rcfile; our rcfile reads the login files instead.
+    pub fn resolve_profile(&self, name: Option<&str>) -> Option<ShellProfile> {
+        let name = name.unwrap_or("default").to_ascii_lowercase();
+        let p = self.profiles().remove(&name)?;
+        let mut env = p.env;
+        if let Some(term) = p.term {
+            env.insert("TERM".into(), term);
+        }
+        if let Some(locale) = p.locale {
+            env.insert("LANG".into(), locale.clone());
+            env.insert("LC_ALL".into(), locale);
+        }
+        let mut args = p.args;
+        let mut markers = false;
+        let hooks = crate::executor::integration::dir().filter(|_| p.integration);
+        match (hooks, Path::new(&p.program).file_name().and_then(|n| n.to_str())) {
+            (Some(dir), Some("bash")) => {
+                args.retain(|a| a != "-l" && a != "--login");
+                for a in args.iter_mut().filter(|a| *a == "-li" || *a == "-il") {
+                    *a = "-i".into();
+                }
+                args.splice(0..0, ["--rcfile".into(), dir.join("bashrc").display().to_string()]);
+                markers = true;
             }
+            (Some(dir), Some("zsh")) => {
+                env.insert("ZDOTDIR".into(), dir.join("zsh").display().to_string());
+                markers = true;
+            }
+            _ => {}
+        }
+        Some(ShellProfile { name, program: p.program, args, env: env.into_iter().collect(), cwd: p.cwd, dir: None, user: None, init: p.init, markers })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles_need_a_program() {
+        let cfg = SandboxConfig::parse("[profiles.py]\nprogram = \"/usr/bin/python3\"\nargs = [\"-i\"]\n").unwrap();
+        assert_eq!(cfg.profiles()["py"].program, "/usr/bin/python3");
+
+        for text in ["[profiles.py]\nargs = [\"-i\"]\n", "[profiles.py]\nprogram = \" \"\n"] {
+            let err = SandboxConfig::parse(text).unwrap_err().to_string();
+            assert_eq!(err, "profile \"py\" has no program");
         }
     }
 }
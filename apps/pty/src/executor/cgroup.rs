@@ -0,0 +1,156 @@
+use crate::config::{CgroupConfig, Limits};
+use crate::models::ResourceUsage;
+use nix::libc;
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const CONTROLLERS: &str = "+memory +cpu +pids";
+const CPU_PERIOD_US: u64 = 100_000;
+const MB: u64 = 1024 * 1024;
+
+/// Parent cgroup under which every job and session gets its own leaf.
+pub struct CgroupRoot {
+    path: PathBuf,
+}
+
+impl CgroupRoot {
+    pub fn init(cfg: &CgroupConfig) -> anyhow::Result<Self> {
+        let path = cfg.root.clone();
+        let parent = path.parent().unwrap_or(Path::new("/"));
+        if !parent.join("cgroup.controllers").exists() {
+            anyhow::bail!("{} is not a cgroup v2 hierarchy", parent.display());
+        }
+        enable_controllers(parent)?;
+        std::fs::create_dir_all(&path)?;
+        // leaves need the controllers delegated from us
+        std::fs::write(path.join("cgroup.subtree_control"), CONTROLLERS)?;
+        Ok(Self { path })
+    }
+
+    pub fn create(&self, id: &str, limits: &Limits) -> anyhow::Result<Cgroup> {
+        let path = self.path.join(id);
+        std::fs::create_dir(&path)?;
+        let cg = Cgroup::new(path)?;
+        cg.write("memory.max", &max_or(limits.mem_mb.saturating_mul(MB)))?;
+        cg.write("pids.max", &max_or(limits.nproc))?;
+        let quota = max_or(limits.cpu_pct.saturating_mul(CPU_PERIOD_US) / 100);
+        cg.write("cpu.max", &format!("{quota} {CPU_PERIOD_US}"))?;
+        Ok(cg)
+    }
+}
+
+/// Enables our controllers in `parent`. When the parent still holds processes
+/// (typically the container root with this server in it), the kernel refuses,
+/// so move ourselves into a sibling leaf first and retry.
+fn enable_controllers(parent: &Path) -> anyhow::Result<()> {
+    let ctl = parent.join("cgroup.subtree_control");
+    match std::fs::write(&ctl, CONTROLLERS) {
+        Err(e) if e.raw_os_error() == Some(libc::EBUSY) => {
+            let leaf = parent.join("isolated-exec-server");
+            std::fs::create_dir_all(&leaf)?;
+            std::fs::write(leaf.join("cgroup.procs"), "0")?;
+            std::fs::write(&ctl, CONTROLLERS)?;
+            Ok(())
+        }
+        r => Ok(r?),
+    }
+}
+
+fn max_or(v: u64) -> String {
+    if v == 0 { "max".into() } else { v.to_string() }
+}
+
+/// One job's or session's cgroup. Killed and removed on drop.
+pub struct Cgroup {
+    path: PathBuf,
+    procs: CString,
+}
+
+impl Cgroup {
+    pub(crate) fn new(path: PathBuf) -> anyhow::Result<Self> {
+        let procs = CString::new(path.join("cgroup.procs").as_os_str().as_bytes())?;
+        Ok(Self { path, procs })
+    }
+
+    fn write(&self, file: &str, value: &str) -> std::io::Result<()> {
+        std::fs::write(self.path.join(file), value)
+    }
+
+    fn read(&self, file: &str) -> Option<String> {
+        std::fs::read_to_string(self.path.join(file)).ok()
+    }
+
+    /// Moves the calling process into this cgroup. Async-signal-safe, so it
+    /// can run in a forked child before exec.
+    pub fn join_self(&self) -> std::io::Result<()> {
+        unsafe {
+            let fd = libc::open(self.procs.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+            if fd < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            let n = libc::write(fd, b"0".as_ptr().cast(), 1);
+            libc::close(fd);
+            if n != 1 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn usage(&self) -> ResourceUsage {
+        let num = |file: &str| self.read(file).and_then(|s| s.trim().parse().ok());
+        ResourceUsage {
+            memory_current: num("memory.current"),
+            memory_max: num("memory.max"),
+            cpu_usage_usec: self.read("cpu.stat").and_then(|s| keyed(&s, "usage_usec")),
+            pids_current: num("pids.current"),
+            pids_max: num("pids.max"),
+            oom_kills: self.read("memory.events").and_then(|s| keyed(&s, "oom_kill")).unwrap_or(0),
+        }
+    }
+
+    /// SIGKILLs every process in the cgroup, orphans included (Linux 5.14+).
+    pub fn kill(&self) -> std::io::Result<()> {
+        self.write("cgroup.kill", "1")
+    }
+
+    /// Whether the OOM killer took anything in this cgroup; it's fresh per
+    /// job or session, so any count means during this one.
+    pub fn oom_killed(&self) -> bool {
+        self.read("memory.events").and_then(|s| keyed(&s, "oom_kill")).unwrap_or(0) > 0
+    }
+
+    #[cfg(test)]
+    pub(crate) fn remove_test_dir(&self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        // take down anything left behind, then rmdir once the kernel has reaped it
+        let _ = self.kill();
+        let path = std::mem::take(&mut self.path);
+        match tokio::runtime::Handle::try_current() {
+            Ok(rt) => {
+                rt.spawn(async move {
+                    for _ in 0..100 {
+                        if std::fs::remove_dir(&path).is_ok() { return; }
+                        tokio::time::sleep(Duration::from_millis(10)).await;
+                    }
+                    tracing::warn!("cgroup {} not removed", path.display());
+                });
+            }
+            Err(_) => { let _ = std::fs::remove_dir(&path); }
+        }
+    }
+}
+
+fn keyed(text: &str, key: &str) -> Option<u64> {
+    text.lines()
+        .filter_map(|l| l.split_once(' '))
+        .find(|(k, _)| *k == key)
+        .and_then(|(_, v)| v.trim().parse().ok())
+}
@@ -0,0 +1,274 @@
+//! Shell integration: bash and zsh sessions get OSC 133 hooks so output can
+//! be split per command. `ESC ]133;C BEL` is printed when a command starts
+//! running and `ESC ]133;D;<status> BEL` before the next prompt. A line
+//! typed by /run sets `__ie_run`, which the end marker then carries as
+//! `ESC ]133;D;<status>;<id> BEL`.
+
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+use crate::executor::pty;
+use crate::io::encoding::{floor_char_boundary, Encoding, OutputDecoder};
+use crate::io::stream::FrameCursor;
+use crate::models::RunResponse;
+use crate::state::SessionHandle;
+
+/// Outside /tmp, which isolated sessions get a fresh tmpfs for.
+const DIR: &str = "/run/isolated-exec/shell";
+
+pub const COMMAND_START: &str = "\x1b]133;C\x07";
+pub const COMMAND_END: &str = "\x1b]133;D;";
+pub const PROMPT_START: &str = "\x1b]133;A\x07";
+
+/// How long a timed-out /run waits for the prompt after sending ^C.
+const PROMPT_WAIT: Duration = Duration::from_secs(2);
+
+const BASH_RC: &str = r#"# bash --rcfile: the usual login startup files, then the OSC 133 hooks
+[ -r /etc/profile ] && . /etc/profile
+if [ -r ~/.bash_profile ]; then . ~/.bash_profile
+elif [ -r ~/.bash_login ]; then . ~/.bash_login
+elif [ -r ~/.profile ]; then . ~/.profile
+fi
+__ie_prompt() { local s=$?; printf '\033]133;D;%s%s\007' "$s" "${__ie_run:+;$__ie_run}"; unset __ie_run; return $s; }
+PROMPT_COMMAND="__ie_prompt${PROMPT_COMMAND:+; $PROMPT_COMMAND}"
+PS0=$'\e]133;C\a'"$PS0"
+PS1="\[\e]133;A\a\]$PS1\[\e]133;B\a\]"
+"#;
+
+// zsh reads every startup file from $ZDOTDIR; each of ours sources the
+// user's copy from $HOME first.
+const ZSH_FILES: [(&str, &str); 4] = [
+    (".zshenv", "__ie_z=$ZDOTDIR; ZDOTDIR=$HOME; [[ -r $ZDOTDIR/.zshenv ]] && . $ZDOTDIR/.zshenv; ZDOTDIR=$__ie_z\n"),
+    (".zprofile", "__ie_z=$ZDOTDIR; ZDOTDIR=$HOME; [[ -r $ZDOTDIR/.zprofile ]] && . $ZDOTDIR/.zprofile; ZDOTDIR=$__ie_z\n"),
+    (".zshrc", r#"__ie_z=$ZDOTDIR; ZDOTDIR=$HOME; [[ -r $ZDOTDIR/.zshrc ]] && . $ZDOTDIR/.zshrc; ZDOTDIR=$__ie_z
+__ie_precmd() { print -n "\e]133;D;$?${__ie_run:+;$__ie_run}\a"; unset __ie_run; }
+__ie_preexec() { print -n "\e]133;C\a"; }
+precmd_functions=(__ie_precmd $precmd_functions)
+preexec_functions+=(__ie_preexec)
+PS1=$'%{\e]133;A\a%}'"$PS1"$'%{\e]133;B\a%}'
+"#),
+    (".zlogin", "ZDOTDIR=$HOME; [[ -r $ZDOTDIR/.zlogin ]] && . $ZDOTDIR/.zlogin\n"),
+];
+
+/// Writes the hook files once; None (and no markers) if that fails.
+pub fn dir() -> Option<&'static Path> {
+    static DIR_OK: OnceLock<Option<PathBuf>> = OnceLock::new();
+    DIR_OK
+        .get_or_init(|| match install(Path::new(DIR)) {
+            Ok(()) => Some(PathBuf::from(DIR)),
+            Err(e) => {
+                tracing::warn!("shell integration disabled: {DIR} ({e})");
+                None
+            }
+        })
+        .as_deref()
+}
+
+fn install(dir: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir.join("zsh"))?;
+    std::fs::write(dir.join("bashrc"), BASH_RC)?;
+    for (name, body) in ZSH_FILES {
+        std::fs::write(dir.join("zsh").join(name), body)?;
+    }
+    Ok(())
+}
+
+/// Types `cmd` into the session and collects its output until the shell
+/// reports its exit status, the session ends, or `timeout` passes. The line
+/// is tagged with a fresh id, so markers of commands typed before it (by
+/// /write or a socket) are skipped rather than taken as its result. On
+/// timeout the command is interrupted (see `interrupt`).
+pub async fn run(h: &SessionHandle, cmd: &str, timeout: Duration) -> anyhow::Result<RunResponse> {
+    let id = uuid::Uuid::new_v4().simple().to_string();
+    let from = *h.latest_seq.lock();
+    let mut cursor = FrameCursor::new(h.backlog.clone(), None, h.tx.subscribe(), from);
+    pty::write_pty(h, &format!("__ie_run={id}; {cmd}\r")).await?;
+
+    let mut out = CommandOutput::new(&id);
+    let mut dec = OutputDecoder::new(Encoding::Utf8);
+    let (mut starts, mut started_ms, mut last_ms) = (0, None, 0);
+    let mut resp = RunResponse { state: "timeout".into(), ..Default::default() };
+    let deadline = tokio::time::sleep(timeout);
+    tokio::pin!(deadline);
+    loop {
+        let f = tokio::select! {
+            f = cursor.next() => f,
+            _ = &mut deadline => {
+                resp.at_prompt = Some(interrupt(h, &mut cursor, &mut dec).await);
+                break;
+            }
+        };
+        let Some(f) = f.filter(|f| !f.is_exit()) else {
+            resp.state = "exited".into();
+            break;
+        };
+        let Some(text) = dec.frame_text(&f) else { continue };
+        let status = out.push(&text);
+        if out.starts != starts {
+            (starts, started_ms) = (out.starts, Some(f.ts_ms));
+        }
+        last_ms = f.ts_ms;
+        if let Some(status) = status {
+            resp.state = "completed".into();
+            resp.exit_code = Some(status);
+            break;
+        }
+    }
+    resp.output = out.output();
+    resp.truncated = out.truncated;
+    resp.duration_ms = started_ms.map(|s| last_ms - s);
+    Ok(resp)
+}
+
+/// Types ^C and waits up to `PROMPT_WAIT` for the next prompt, so neither a
+/// command still running nor a line the shell never ran (an unclosed quote
+/// leaves it at PS2) swallows the next /run. Returns whether the prompt came.
+async fn interrupt(h: &SessionHandle, cursor: &mut FrameCursor, dec: &mut OutputDecoder) -> bool {
+    if let Err(e) = pty::write_pty(h, "\x03").await {
+        tracing::debug!("run: interrupt failed: {e}");
+        return false;
+    }
+    let mut tail = String::new();
+    let wait = tokio::time::sleep(PROMPT_WAIT);
+    tokio::pin!(wait);
+    loop {
+        let f = tokio::select! {
+            f = cursor.next() => f,
+            _ = &mut wait => return false,
+        };
+        let Some(f) = f.filter(|f| !f.is_exit()) else { return false };
+        let Some(text) = dec.frame_text(&f) else { continue };
+        tail.push_str(&text);
+        if tail.contains(PROMPT_START) {
+            return true;
+        }
+        let keep = floor_char_boundary(&tail, tail.len().saturating_sub(PROMPT_START.len()));
+        tail.drain(..keep);
+    }
+}
+
+/// Follows a session's output for one command: text after `COMMAND_START`
+/// up to a `COMMAND_END` carrying `id`. An end marker without it belongs to
+/// an earlier command, so the text collected so far is dropped and the next
+/// start marker begins again. Past `MAX_OUTPUT` bytes the oldest half is dropped.
+pub struct CommandOutput {
+    id: String,
+    started: bool,
+    /// output so far; before the start marker, unscanned text
+    text: String,
+    /// start markers seen, so callers can tell when output began again
+    pub starts: usize,
+    pub truncated: bool,
+}
+
+const MAX_OUTPUT: usize = 1 << 20;
+
+/// Longest `<status>;<id>` looked back for when an end marker is split across chunks.
+const END_PARAMS: usize = 48;
+
+impl CommandOutput {
+    pub fn new(id: &str) -> Self {
+        Self { id: id.into(), started: false, text: String::new(), starts: 0, truncated: false }
+    }
+
+    /// Adds output; returns the exit status once this command's end marker has been seen.
+    pub fn push(&mut self, chunk: &str) -> Option<i32> {
+        let mut scan_from = self.text.len().saturating_sub(COMMAND_END.len() + END_PARAMS);
+        self.text.push_str(chunk);
+        loop {
+            if !self.started {
+                let Some(at) = self.text.find(COMMAND_START) else {
+                    let keep = floor_char_boundary(&self.text, self.text.len().saturating_sub(COMMAND_START.len()));
+                    self.text.drain(..keep);
+                    return None;
+                };
+                self.text.drain(..at + COMMAND_START.len());
+                self.started = true;
+                self.starts += 1;
+                self.truncated = false;
+                scan_from = 0;
+            }
+            let at = floor_char_boundary(&self.text, scan_from);
+            let Some(i) = self.text[at..].find(COMMAND_END).map(|i| i + at) else { break };
+            let rest = &self.text[i + COMMAND_END.len()..];
+            let end = rest.find('\x07')?;
+            let (status, id) = rest[..end].split_once(';').unwrap_or((&rest[..end], ""));
+            if id == self.id {
+                let status = status.parse().unwrap_or(-1);
+                self.text.truncate(i);
+                return Some(status);
+            }
+            self.text.drain(..i + COMMAND_END.len() + end + 1);
+            self.started = false;
+        }
+        if self.text.len() > MAX_OUTPUT {
+            let cut = floor_char_boundary(&self.text, self.text.len() - MAX_OUTPUT / 2);
+            self.text.drain(..cut);
+            self.truncated = true;
+        }
+        None
+    }
+
+    /// The command's output with CRLF turned into LF; empty before it started.
+    pub fn output(&self) -> String {
+        if self.started { self.text.replace("\r\n", "\n") } else { String::new() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START: &str = COMMAND_START;
+
+    fn end(status: i32, id: &str) -> String {
+        if id.is_empty() { format!("{COMMAND_END}{status}\x07") } else { format!("{COMMAND_END}{status};{id}\x07") }
+    }
+
+    #[test]
+    fn collects_output_between_markers() {
+        let mut out = CommandOutput::new("r1");
+        assert_eq!(out.push("$ echo hi\r\n"), None);
+        assert_eq!(out.push(&format!("{START}hi\r\n")), None);
+        assert_eq!(out.push(&format!("{}\x1b]133;A\x07$ ", end(3, "r1"))), Some(3));
+        assert_eq!(out.output(), "hi\n");
+        assert_eq!(out.starts, 1);
+    }
+
+    #[test]
+    fn markers_split_across_chunks() {
+        let mut out = CommandOutput::new("r1");
+        let all = format!("noise{START}a€b{}", end(0, "r1"));
+        let mut status = None;
+        for (i, c) in all.char_indices() {
+            assert_eq!(status, None, "ended early at {i}");
+            status = out.push(&c.to_string());
+        }
+        assert_eq!(status, Some(0));
+        assert_eq!(out.output(), "a€b");
+    }
+
+    #[test]
+    fn skips_an_earlier_commands_result() {
+        // `echo pwned` typed by /write just before the run's own line
+        let mut out = CommandOutput::new("r2");
+        let chunk = format!("{START}pwned\r\n{}$ {START}ran\r\n", end(0, ""));
+        assert_eq!(out.push(&chunk), None);
+        assert_eq!(out.push(&end(1, "r1")), None);
+        assert_eq!(out.output(), "");
+        assert_eq!(out.push(&format!("{START}ran\r\n{}", end(1, "r2"))), Some(1));
+        assert_eq!(out.output(), "ran\n");
+        assert_eq!(out.starts, 3);
+    }
+
+    #[test]
+    fn keeps_the_newest_output_past_the_limit() {
+        let mut out = CommandOutput::new("r1");
+        out.push(START);
+        out.push(&"x".repeat(MAX_OUTPUT));
+        out.push("tail");
+        assert!(out.truncated);
+        assert_eq!(out.push(&end(0, "r1")), Some(0));
+        assert!(out.output().ends_with("xtail") && out.output().len() <= MAX_OUTPUT);
+    }
+}
@@ -0,0 +1,59 @@
+use crate::config::Limits;
+use crate::executor::cgroup::Cgroup;
+use crate::executor::limits::apply_rlimits;
+use crate::executor::namespaces::NsPlan;
+use crate::executor::seccomp::{SeccompChild, SeccompListener};
+use nix::libc;
+use std::sync::Arc;
+
+/// Per-child confinement, applied between fork and exec by both executors:
+/// `enter` first, then any uid and directory switch, then `seal`.
+pub struct Isolation {
+    pub limits: Limits,
+    pub cgroup: Option<Arc<Cgroup>>,
+    pub namespaces: Option<Arc<NsPlan>>,
+    pub seccomp: Option<SeccompChild>,
+    /// taken by the executor before fork; answers the child's blocked syscalls
+    pub seccomp_listener: Option<SeccompListener>,
+}
+
+impl Isolation {
+    /// # Safety
+    /// Must only be called in a freshly forked child, before exec.
+    pub unsafe fn enter(&self) -> std::io::Result<()> {
+        reset_signals();
+        if let Some(cg) = &self.cgroup {
+            cg.join_self()?;
+        }
+        if let Some(ns) = &self.namespaces {
+            ns.enter()?;
+        }
+        apply_rlimits(&self.limits)?;
+        Ok(())
+    }
+
+    /// Installs the seccomp filter, so nothing the child still has to do
+    /// before exec runs under it.
+    ///
+    /// # Safety
+    /// Must only be called in a freshly forked child, right before exec.
+    pub unsafe fn seal(&self) -> std::io::Result<()> {
+        if let Some(sc) = &self.seccomp {
+            sc.install()?;
+        }
+        Ok(())
+    }
+}
+
+/// Ignored dispositions and the blocked mask survive exec; the child should
+/// not inherit the server's (e.g. SIGINT ignored when started in the background).
+unsafe fn reset_signals() {
+    for sig in 1..libc::SIGRTMIN() {
+        if sig != libc::SIGKILL && sig != libc::SIGSTOP {
+            libc::signal(sig, libc::SIG_DFL);
+        }
+    }
+    let mut set = std::mem::zeroed::<libc::sigset_t>();
+    libc::sigemptyset(&mut set);
+    libc::sigprocmask(libc::SIG_SETMASK, &set, std::ptr::null_mut());
+}
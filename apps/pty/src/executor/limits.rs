@@ -0,0 +1,27 @@
+use crate::config::Limits;
+use nix::sys::resource::{getrlimit, setrlimit, Resource};
+
+const MB: u64 = 1024 * 1024;
+
+/// Applies `limits` to the calling process. Only async-signal-safe calls are
+/// made, so this is safe to run in a forked child before exec.
+pub fn apply_rlimits(limits: &Limits) -> nix::Result<()> {
+    set(Resource::RLIMIT_CPU, limits.cpu_ms.div_ceil(1000))?;
+    // RLIMIT_DATA, not RLIMIT_AS: V8, the JVM and Go reserve far more address
+    // space than they touch and die on start under an address-space cap
+    set(Resource::RLIMIT_DATA, limits.mem_mb.saturating_mul(MB))?;
+    set(Resource::RLIMIT_FSIZE, limits.fsize_mb.saturating_mul(MB))?;
+    set(Resource::RLIMIT_NPROC, limits.nproc)?;
+    set(Resource::RLIMIT_NOFILE, limits.nofile)?;
+    Ok(())
+}
+
+fn set(res: Resource, value: u64) -> nix::Result<()> {
+    if value == 0 {
+        return Ok(());
+    }
+    // never try to raise above the inherited hard limit
+    let (_, hard) = getrlimit(res)?;
+    let v = value.min(hard);
+    setrlimit(res, v, v)
+}
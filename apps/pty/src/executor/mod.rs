@@ -1,2 +1,76 @@
+pub mod cgroup;
+pub mod integration;
+pub mod isolation;
+pub mod limits;
+pub mod namespaces;
 pub mod pty;
+pub mod seccomp;
 pub mod spawn;
+
+use cgroup::Cgroup;
+use nix::sys::signal::Signal;
+
+/// `d` of the final event for a job or session: "exit:Some(0)" for a normal
+/// exit, "exit:SIGTERM" when killed by a signal, "exit:oom" when it died of a
+/// signal or exited with 128+ after the cgroup OOM killer struck. The victim
+/// is often a grandchild, e.g. under `bash -c 'a | b'` bash itself exits 137.
+pub fn exit_event(code: Option<i32>, signal: Option<i32>, cgroup: Option<&Cgroup>) -> String {
+    let abnormal = signal.is_some() || code.is_some_and(|c| c >= 128);
+    if abnormal && cgroup.is_some_and(|cg| cg.oom_killed()) {
+        return "exit:oom".into();
+    }
+    match signal {
+        Some(sig) => format!("exit:{}", signal_name(sig)),
+        None => format!("exit:{code:?}"),
+    }
+}
+
+pub fn signal_name(sig: i32) -> String {
+    Signal::try_from(sig).map(|s| s.as_str().to_string()).unwrap_or_else(|_| format!("SIG{sig}"))
+}
+
+/// Signals accepted by the signal endpoints.
+pub const SIGNALS: [&str; 6] = ["INT", "TERM", "HUP", "TSTP", "CONT", "KILL"];
+
+/// Parses one of `SIGNALS`, with or without the "SIG" prefix.
+pub fn parse_signal(name: &str) -> Option<Signal> {
+    let name = name.to_ascii_uppercase();
+    let short = name.strip_prefix("SIG").unwrap_or(&name);
+    if !SIGNALS.contains(&short) {
+        return None;
+    }
+    format!("SIG{short}").parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory standing in for a job's cgroup, with the given oom_kill count.
+    fn fake_cgroup(name: &str, oom_kills: u32) -> Cgroup {
+        let dir = std::env::temp_dir().join(format!("ie-test-cgroup-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("memory.events"), format!("low 0\nhigh 0\nmax 3\noom 1\noom_kill {oom_kills}\n")).unwrap();
+        Cgroup::new(dir).unwrap()
+    }
+
+    #[test]
+    fn oom_kill_of_a_grandchild_is_reported() {
+        let cg = fake_cgroup("oom", 1);
+        assert_eq!(exit_event(Some(137), None, Some(&cg)), "exit:oom");
+        assert_eq!(exit_event(None, Some(9), Some(&cg)), "exit:oom");
+        // the job got past it on its own
+        assert_eq!(exit_event(Some(0), None, Some(&cg)), "exit:Some(0)");
+        assert_eq!(exit_event(Some(1), None, Some(&cg)), "exit:Some(1)");
+        cg.remove_test_dir();
+    }
+
+    #[test]
+    fn plain_exits_without_oom_kills() {
+        let cg = fake_cgroup("plain", 0);
+        assert_eq!(exit_event(Some(137), None, Some(&cg)), "exit:Some(137)");
+        assert_eq!(exit_event(None, Some(9), Some(&cg)), "exit:SIGKILL");
+        assert_eq!(exit_event(Some(137), None, None), "exit:Some(137)");
+        cg.remove_test_dir();
+    }
+}
@@ -0,0 +1,151 @@
+use crate::config::NamespaceConfig;
+use nix::libc;
+use std::ffi::{CStr, CString};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+/// Everything needed to enter fresh namespaces from a forked child,
+/// prepared up front so the child itself never allocates.
+pub struct NsPlan {
+    flags: libc::c_int,
+    pid: bool,
+    mount: bool,
+    user: bool,
+    net: bool,
+    uid_map: CString,
+    gid_map: CString,
+    sandbox_src: Option<CString>,
+}
+
+impl NsPlan {
+    pub fn new(cfg: &NamespaceConfig) -> anyhow::Result<Self> {
+        let mut flags = 0;
+        if cfg.user { flags |= libc::CLONE_NEWUSER; }
+        if cfg.pid { flags |= libc::CLONE_NEWPID; }
+        if cfg.mount { flags |= libc::CLONE_NEWNS; }
+        if cfg.net { flags |= libc::CLONE_NEWNET; }
+        let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+        let sandbox_src = if cfg.sandbox.as_os_str() != "/sandbox" {
+            Some(CString::new(cfg.sandbox.as_os_str().as_bytes())?)
+        } else {
+            None
+        };
+        Ok(Self {
+            flags,
+            pid: cfg.pid,
+            mount: cfg.mount,
+            user: cfg.user,
+            net: cfg.net,
+            uid_map: CString::new(format!("0 {uid} 1"))?,
+            gid_map: CString::new(format!("0 {gid} 1"))?,
+            sandbox_src,
+        })
+    }
+
+    /// Whether a user namespace is created; it maps only root, so no other
+    /// uid or gid can be switched to inside.
+    pub fn user(&self) -> bool {
+        self.user
+    }
+
+    /// Unshares the configured namespaces. With a PID namespace the calling
+    /// process stays behind as a supervisor that mirrors the exit status of
+    /// the new namespace's init; only that init returns from here.
+    ///
+    /// # Safety
+    /// Must only be called in a freshly forked child, before exec.
+    pub unsafe fn enter(&self) -> io::Result<()> {
+        check(libc::unshare(self.flags))?;
+        if self.user {
+            write_file(c"/proc/self/setgroups", b"deny")?;
+            write_file(c"/proc/self/uid_map", self.uid_map.as_bytes())?;
+            write_file(c"/proc/self/gid_map", self.gid_map.as_bytes())?;
+        }
+        if self.pid {
+            let pid = check(libc::fork())?;
+            if pid > 0 {
+                supervise(pid);
+            }
+            // the supervisor going away takes the whole namespace with it
+            check(libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL))?;
+        }
+        if self.mount {
+            check(libc::mount(std::ptr::null(), c"/".as_ptr(), std::ptr::null(),
+                libc::MS_REC | libc::MS_PRIVATE, std::ptr::null()))?;
+            if let Some(src) = &self.sandbox_src {
+                check(libc::mount(src.as_ptr(), c"/sandbox".as_ptr(), std::ptr::null(),
+                    libc::MS_BIND | libc::MS_REC, std::ptr::null()))?;
+            }
+            check(libc::mount(c"tmpfs".as_ptr(), c"/tmp".as_ptr(), c"tmpfs".as_ptr(),
+                libc::MS_NOSUID | libc::MS_NODEV, c"mode=1777".as_ptr().cast()))?;
+            if self.pid {
+                check(libc::mount(c"proc".as_ptr(), c"/proc".as_ptr(), c"proc".as_ptr(),
+                    libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC, std::ptr::null()))?;
+            }
+        }
+        if self.net {
+            loopback_up()?;
+        }
+        Ok(())
+    }
+}
+
+static INNER_PID: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn forward(sig: libc::c_int) {
+    unsafe { libc::kill(INNER_PID.load(Ordering::Relaxed), sig); }
+}
+
+/// Waits for the namespace init, relaying termination signals to it, then
+/// exits with the same status.
+unsafe fn supervise(pid: libc::pid_t) -> ! {
+    // drop inherited fds (e.g. std's exec-status pipe) so the spawner isn't held up by us
+    libc::syscall(libc::SYS_close_range, 3u32, u32::MAX, 0u32);
+    INNER_PID.store(pid, Ordering::Relaxed);
+    for sig in [libc::SIGTERM, libc::SIGHUP, libc::SIGINT, libc::SIGQUIT, libc::SIGUSR1, libc::SIGUSR2] {
+        libc::signal(sig, forward as *const () as libc::sighandler_t);
+    }
+    for sig in [libc::SIGTSTP, libc::SIGTTIN, libc::SIGTTOU] {
+        libc::signal(sig, libc::SIG_IGN);
+    }
+    let mut status = 0;
+    while libc::waitpid(pid, &mut status, 0) < 0 {
+        if *libc::__errno_location() != libc::EINTR { libc::_exit(127); }
+    }
+    if libc::WIFSIGNALED(status) {
+        let sig = libc::WTERMSIG(status);
+        libc::signal(sig, libc::SIG_DFL);
+        libc::kill(libc::getpid(), sig);
+        libc::_exit(128 + sig);
+    }
+    libc::_exit(libc::WEXITSTATUS(status))
+}
+
+unsafe fn write_file(path: &CStr, data: &[u8]) -> io::Result<()> {
+    let fd = check(libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC))?;
+    let n = libc::write(fd, data.as_ptr().cast(), data.len());
+    libc::close(fd);
+    if n != data.len() as isize {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+unsafe fn loopback_up() -> io::Result<()> {
+    let sock = check(libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0))?;
+    let mut req: libc::ifreq = std::mem::zeroed();
+    for (dst, src) in req.ifr_name.iter_mut().zip(b"lo") {
+        *dst = *src as libc::c_char;
+    }
+    let res = check(libc::ioctl(sock, libc::SIOCGIFFLAGS, &mut req)).and_then(|_| {
+        req.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short;
+        check(libc::ioctl(sock, libc::SIOCSIFFLAGS, &req))
+    });
+    libc::close(sock);
+    res.map(|_| ())
+}
+
+fn check(rc: libc::c_int) -> io::Result<libc::c_int> {
+    if rc < 0 { Err(io::Error::last_os_error()) } else { Ok(rc) }
+}
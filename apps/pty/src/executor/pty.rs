@@ -1,29 +1,54 @@
 use crate::models::StreamFrame;
 use crate::state::SessionHandle;
-use crate::config::resolve_profile;
+use crate::state::collab::Collab;
+use crate::config::ShellProfile;
+use crate::executor::exit_event;
+use crate::executor::isolation::Isolation;
+use crate::io::encoding::{Encoding, OutputDecoder};
+use crate::io::log::FrameLog;
 
 use parking_lot::Mutex;
 use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Instant, SystemTime};
 use tracing::debug;
 
 use nix::fcntl::{fcntl, FcntlArg, OFlag};
 use nix::libc;
+use nix::errno::Errno;
 use nix::pty::{forkpty, ForkptyResult, Winsize};
-use nix::unistd::{dup, execvp, read as nix_read, write as nix_write};
+use nix::sys::signal::{kill, killpg, Signal};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::{dup, execvpe, read as nix_read, tcgetpgrp, Pid};
 use std::ffi::CString;
-use std::os::fd::{AsRawFd, FromRawFd, IntoRawFd, BorrowedFd};
+use std::os::fd::{AsRawFd, FromRawFd, IntoRawFd, BorrowedFd, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
 use tokio::io::unix::AsyncFd;
 
-pub fn spawn_pty_shell(profile: Option<String>, cols: u16, rows: u16) -> anyhow::Result<SessionHandle> {
-    let prof = resolve_profile(profile.as_deref());
+pub fn spawn_pty_shell(
+    prof: ShellProfile,
+    cols: u16,
+    rows: u16,
+    mut iso: Isolation,
+    enc: Encoding,
+    log: Option<Arc<FrameLog>>,
+) -> anyhow::Result<SessionHandle> {
     let prog = CString::new(prof.program.clone()).expect("prog CString");
     let mut argv: Vec<CString> = Vec::with_capacity(1 + prof.args.len());
     argv.push(prog.clone());
     for a in &prof.args {
         argv.push(CString::new(a.as_str()).expect("arg CString"));
     }
+    // built before forking: the child must not allocate
+    let envp: Vec<CString> = std::env::vars()
+        .filter(|(k, _)| !prof.env.iter().any(|(pk, _)| pk == k))
+        .chain(prof.env.iter().cloned())
+        .filter_map(|(k, v)| CString::new(format!("{k}={v}")).ok())
+        .collect();
+    let cwd = prof.cwd.as_ref().map(|d| CString::new(d.as_os_str().as_bytes())).transpose()?;
 
+    let seccomp_listener = iso.seccomp_listener.take();
     let ws = Winsize { ws_row: rows, ws_col: cols, ws_xpixel: 0, ws_ypixel: 0 };
 
     let fp = unsafe { forkpty(Some(&ws), None)? };
@@ -31,11 +56,38 @@ pub fn spawn_pty_shell(profile: Option<String>, cols: u16, rows: u16) -> anyhow:
     match fp {
         ForkptyResult::Child => {
             unsafe {
-                let _ = execvp(&prog, &argv);
+                if iso.enter().is_err() {
+                    let msg = b"isolated-exec: sandbox setup failed\r\n";
+                    libc::write(2, msg.as_ptr().cast(), msg.len());
+                    libc::_exit(126);
+                }
+                if let Some(u) = prof.user {
+                    if libc::setgroups(1, &u.gid) != 0 || libc::setgid(u.gid) != 0 || libc::setuid(u.uid) != 0 {
+                        let msg = b"isolated-exec: cannot switch uid/gid\r\n";
+                        libc::write(2, msg.as_ptr().cast(), msg.len());
+                        libc::_exit(126);
+                    }
+                }
+                let moved = match (&prof.dir, &cwd) {
+                    (Some(dir), _) => libc::fchdir(dir.as_raw_fd()) == 0,
+                    (None, Some(path)) => libc::chdir(path.as_ptr()) == 0,
+                    (None, None) => true,
+                };
+                if !moved {
+                    let msg = b"isolated-exec: chdir failed\r\n";
+                    libc::write(2, msg.as_ptr().cast(), msg.len());
+                    libc::_exit(126);
+                }
+                if iso.seal().is_err() {
+                    let msg = b"isolated-exec: sandbox setup failed\r\n";
+                    libc::write(2, msg.as_ptr().cast(), msg.len());
+                    libc::_exit(126);
+                }
+                let _ = execvpe(&prog, &argv, &envp);
+                let msg = b"isolated-exec: exec failed\r\n";
+                libc::write(2, msg.as_ptr().cast(), msg.len());
                 libc::_exit(127);
             }
-            #[allow(unreachable_code)]
-            unreachable!("execvp should not return");
         }
         ForkptyResult::Parent { child, master } => {
             let mfd = master.as_raw_fd();
@@ -46,86 +98,154 @@ pub fn spawn_pty_shell(profile: Option<String>, cols: u16, rows: u16) -> anyhow:
             let rd_file = unsafe { std::fs::File::from_raw_fd(rd_raw) };
             let wr_file = unsafe { std::fs::File::from_raw_fd(master.into_raw_fd()) };
 
-            let reader = Arc::new(AsyncFd::new(rd_file)?);
-            let writer = Arc::new(AsyncFd::new(wr_file)?);
+            // SAFETY: both files own their fds for the lifetime of the AsyncFd
+            let reader = Arc::new(unsafe { AsyncFd::register(rd_file)? });
+            let writer = Arc::new(unsafe { AsyncFd::register(wr_file)? });
 
             let (tx, _rx) = tokio::sync::broadcast::channel::<StreamFrame>(1024);
-            let latest_seq = Arc::new(Mutex::new(0u64));
-            let exit_code  = Arc::new(Mutex::new(None));
-            let backlog    = Arc::new(Mutex::new(VecDeque::with_capacity(1024)));
+            let cgroup = iso.cgroup.clone();
+            let h = SessionHandle {
+                latest_seq: Arc::new(Mutex::new(0u64)),
+                tx,
+                exit_code: Arc::new(Mutex::new(None)),
+                exit_signal: Arc::new(Mutex::new(None)),
+                reader,
+                writer,
+                pid: child.as_raw(),
+                backlog: Arc::new(Mutex::new(VecDeque::with_capacity(1024))),
+                cgroup,
+                finished_at: Arc::new(Mutex::new(None)),
+                last_activity: Arc::new(Mutex::new(Instant::now())),
+                hung_up: Arc::new(AtomicBool::new(false)),
+                idle_killed: Arc::new(AtomicBool::new(false)),
+                log,
+                started: Instant::now(),
+                created_at: SystemTime::now(),
+                initial_size: (cols, rows),
+                shell: prof.program.clone(),
+                markers: prof.markers,
+                run_lock: Arc::new(tokio::sync::Mutex::new(())),
+                collab: Arc::new(Mutex::new(Collab::default())),
+                profile: prof.name.clone(),
+                bytes: Arc::new(AtomicU64::new(0)),
+                screen: Arc::new(Mutex::new(vt100::Parser::new(rows, cols, 0))),
+            };
 
-            let txr = tx.clone();
-            let seqr = latest_seq.clone();
-            let backlog_c = backlog.clone();
-            let reader_c = reader.clone();
+            // Reap the shell; the reader reports its status once output is drained
+            let waiter = tokio::spawn(wait_child(child));
 
+            // close our copy of the child's seccomp socket so a child that dies early unblocks the listener
+            drop(iso);
+            if let Some(listener) = seccomp_listener {
+                let h = h.clone();
+                listener.spawn(move |ev| h.push_event(ev));
+            }
+
+            let hc = h.clone();
             tokio::spawn(async move {
+                let h = hc;
                 let mut buf = [0u8; 4096];
-                loop {
-                    match reader_c.readable().await {
-                        Ok(mut guard) => {
-                            let res = guard.try_io(|inner| {
-                                let fd = inner.get_ref().as_raw_fd();
-                                match nix_read(fd, &mut buf) {
-                                    Ok(n) => Ok(n),
-                                    Err(e) => Err(std::io::Error::from_raw_os_error(e as i32)),
-                                }
-                            });
-                            match res {
-                                Ok(Ok(0)) => {
-                                    debug!("PTY EOF");
-                                    let mut s = seqr.lock(); *s += 1;
-                                    let ev = StreamFrame { t: "event".into(), seq: *s, d: "exit:None".into() };
-                                    let mut b = backlog_c.lock();
-                                    if b.len() == b.capacity() { b.pop_front(); }
-                                    b.push_back(ev.clone());
-                                    let _ = txr.send(ev);
-                                    break;
-                                }
-                                Ok(Ok(n)) => {
-                                    debug!("PTY read {} bytes", n);
-                                    let sdata = String::from_utf8_lossy(&buf[..n]).to_string();
-                                    let mut s = seqr.lock(); *s += 1;
-                                    let frame = StreamFrame { t: "stdout".into(), seq: *s, d: sdata };
-                                    let mut b = backlog_c.lock();
-                                    if b.len() == b.capacity() { b.pop_front(); }
-                                    b.push_back(frame.clone());
-                                    let _ = txr.send(frame);
-                                }
-                                Ok(Err(e)) => {
-                                    if e.kind() == std::io::ErrorKind::WouldBlock { continue; }
-                                    debug!("PTY read error: {}", e);
-                                    let mut s = seqr.lock(); *s += 1;
-                                    let ev = StreamFrame { t: "event".into(), seq: *s, d: "exit:None".into() };
-                                    let mut b = backlog_c.lock();
-                                    if b.len() == b.capacity() { b.pop_front(); }
-                                    b.push_back(ev.clone());
-                                    let _ = txr.send(ev);
-                                    break;
-                                }
-                                Err(_would_block) => continue,
-                            }
+                let mut dec = OutputDecoder::new(enc);
+                let finish = |(code, signal): (Option<i32>, Option<i32>)| {
+                    *h.exit_code.lock() = code;
+                    *h.exit_signal.lock() = signal;
+                    *h.finished_at.lock() = Some(Instant::now());
+                    h.push_event(exit_event(code, signal, h.cgroup.as_deref()));
+                    if let Some(log) = &h.log { log.finish(); }
+                };
+                while let Ok(mut guard) = h.reader.readable().await {
+                    let res = guard.try_io(|inner| {
+                        let fd = inner.get_ref().as_raw_fd();
+                        match nix_read(fd, &mut buf) {
+                            Ok(n) => Ok(n),
+                            Err(e) => Err(std::io::Error::from_raw_os_error(e as i32)),
+                        }
+                    });
+                    match res {
+                        Ok(Ok(0)) => {
+                            debug!("PTY EOF");
+                            if let Some(d) = dec.finish() { h.emit("stdout", d, dec.tag()); }
+                            finish(waiter.await.unwrap_or_default());
+                            break;
+                        }
+                        Ok(Ok(n)) => {
+                            debug!("PTY read {} bytes", n);
+                            h.bytes.fetch_add(n as u64, Ordering::Relaxed);
+                            h.emit_output(&buf[..n], dec.decode(&buf[..n]), dec.tag());
+                        }
+                        Ok(Err(e)) => {
+                            if e.kind() == std::io::ErrorKind::WouldBlock { continue; }
+                            debug!("PTY read error: {}", e);
+                            if let Some(d) = dec.finish() { h.emit("stdout", d, dec.tag()); }
+                            finish(waiter.await.unwrap_or_default());
+                            break;
                         }
-                        Err(_e) => break,
+                        Err(_would_block) => continue,
                     }
                 }
             });
 
-            return Ok(SessionHandle {
-                latest_seq,
-                tx,
-                exit_code,
-                reader,
-                writer,
-                pid: child.as_raw(),
-                backlog,
-            });
+            // sits in the terminal's input queue until the program reads it
+            if let Some(init) = prof.init {
+                let h = h.clone();
+                tokio::spawn(async move {
+                    let init = if init.ends_with('\n') { init } else { init + "\n" };
+                    if let Err(e) = write_pty(&h, &init).await {
+                        debug!("profile init: {}", e);
+                    }
+                });
+            }
+
+            Ok(h)
+        }
+    }
+}
+
+/// Waits for `pid` to exit without tying up a thread: its pidfd turns
+/// readable once it's a zombie. Returns its exit code or terminating signal.
+async fn wait_child(pid: Pid) -> (Option<i32>, Option<i32>) {
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid.as_raw(), 0) };
+    // SAFETY: fd was just opened and is owned by the AsyncFd
+    let pidfd = match fd {
+        0.. => unsafe { AsyncFd::register(OwnedFd::from_raw_fd(fd as RawFd)) }.map_err(|e| e.into()),
+        _ => Err(std::io::Error::last_os_error()),
+    };
+    let pidfd = match pidfd {
+        Ok(p) => p,
+        Err(e) => {
+            // pre-5.3 kernel
+            debug!("pidfd_open {}: {}", pid, e);
+            return tokio::task::spawn_blocking(move || reap(pid, None)).await.ok().flatten().unwrap_or_default();
+        }
+    };
+    loop {
+        let Ok(mut guard) = pidfd.readable().await else { return (None, None) };
+        match reap(pid, Some(WaitPidFlag::WNOHANG)) {
+            Some(status) => return status,
+            None => guard.clear_ready(),
+        }
+    }
+}
+
+/// Reaps `pid`, blocking unless `flags` has WNOHANG; None if it's still
+/// running, (None, None) if it can't be waited for.
+fn reap(pid: Pid, flags: Option<WaitPidFlag>) -> Option<(Option<i32>, Option<i32>)> {
+    loop {
+        match waitpid(pid, flags) {
+            Ok(WaitStatus::Exited(_, code)) => return Some((Some(code), None)),
+            Ok(WaitStatus::Signaled(_, sig, _)) => return Some((None, Some(sig as i32))),
+            Ok(WaitStatus::StillAlive) => return None,
+            Ok(_) | Err(Errno::EINTR) => continue,
+            Err(e) => {
+                debug!("waitpid {} failed: {}", pid, e);
+                return Some((None, None));
+            }
         }
     }
 }
 
 pub async fn write_pty(h: &SessionHandle, data: &str) -> anyhow::Result<()> {
-    use std::os::fd::AsFd;
+    h.touch();
     let writer = h.writer.clone();
     let bytes = data.as_bytes();
     let mut off = 0usize;
@@ -159,10 +279,25 @@ pub async fn resize_pty(h:&SessionHandle, cols:u16, rows:u16) -> anyhow::Result<
     if rc != 0 {
         return Err(anyhow::anyhow!("ioctl TIOCSWINSZ failed: {}", std::io::Error::last_os_error()));
     }
+    let mut screen = h.screen.lock();
+    screen.screen_mut().set_size(rows, cols);
+    h.push_event(format!("resize:{cols}x{rows}"));
     Ok(())
 }
 
+/// Sends `sig` to the shell, or with `group` to the terminal's foreground
+/// process group (what a keypress like ^C would reach).
+pub fn signal_pty(h: &SessionHandle, sig: Signal, group: bool) -> nix::Result<()> {
+    if !group {
+        return kill(Pid::from_raw(h.pid), sig);
+    }
+    let fd = unsafe { BorrowedFd::borrow_raw(h.reader.as_raw_fd()) };
+    let pgrp = tcgetpgrp(fd).unwrap_or(Pid::from_raw(h.pid));
+    killpg(pgrp, sig)
+}
+
 pub async fn close_pty(h:&SessionHandle) -> anyhow::Result<()> {
+    h.push_event("close".into());
     let _ = write_pty(h, "\x04").await;
     Ok(())
 }
@@ -0,0 +1,339 @@
+use nix::libc;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use tokio::io::unix::AsyncFd;
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+/// x32 ABI syscalls on x86_64 carry this bit; they bypass a plain nr match.
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+const SECCOMP_DATA_NR: u32 = 0;
+const SECCOMP_DATA_ARCH: u32 = 4;
+const SECCOMP_DATA_ARG0: u32 = 16;
+
+pub const PROFILES: &[&str] = &["none", "default", "strict", "no-network"];
+
+/// Host administration; never needed by a job.
+const BLOCK_DEFAULT: &[(libc::c_long, &str)] = &[
+    (libc::SYS_mount, "mount"),
+    (libc::SYS_umount2, "umount2"),
+    (libc::SYS_pivot_root, "pivot_root"),
+    (libc::SYS_swapon, "swapon"),
+    (libc::SYS_swapoff, "swapoff"),
+    (libc::SYS_reboot, "reboot"),
+    (libc::SYS_kexec_load, "kexec_load"),
+    (libc::SYS_kexec_file_load, "kexec_file_load"),
+    (libc::SYS_init_module, "init_module"),
+    (libc::SYS_finit_module, "finit_module"),
+    (libc::SYS_delete_module, "delete_module"),
+    (libc::SYS_bpf, "bpf"),
+    (libc::SYS_perf_event_open, "perf_event_open"),
+    (libc::SYS_keyctl, "keyctl"),
+    (libc::SYS_add_key, "add_key"),
+    (libc::SYS_request_key, "request_key"),
+    (libc::SYS_acct, "acct"),
+    (libc::SYS_settimeofday, "settimeofday"),
+    (libc::SYS_clock_settime, "clock_settime"),
+    (libc::SYS_clock_adjtime, "clock_adjtime"),
+    (libc::SYS_adjtimex, "adjtimex"),
+    (libc::SYS_open_by_handle_at, "open_by_handle_at"),
+    (libc::SYS_name_to_handle_at, "name_to_handle_at"),
+    (libc::SYS_setns, "setns"),
+    (libc::SYS_syslog, "syslog"),
+    (libc::SYS_quotactl, "quotactl"),
+    (libc::SYS_userfaultfd, "userfaultfd"),
+    (libc::SYS_fsopen, "fsopen"),
+    (libc::SYS_fsmount, "fsmount"),
+    (libc::SYS_move_mount, "move_mount"),
+    (libc::SYS_open_tree, "open_tree"),
+    // io_uring submits socket, connect, openat... without passing through this filter
+    (libc::SYS_io_uring_setup, "io_uring_setup"),
+    (libc::SYS_io_uring_enter, "io_uring_enter"),
+    (libc::SYS_io_uring_register, "io_uring_register"),
+];
+
+/// On top of the default set: no debugging or inspecting other processes,
+/// no new namespaces, no device nodes.
+const BLOCK_STRICT: &[(libc::c_long, &str)] = &[
+    (libc::SYS_ptrace, "ptrace"),
+    (libc::SYS_process_vm_readv, "process_vm_readv"),
+    (libc::SYS_process_vm_writev, "process_vm_writev"),
+    (libc::SYS_kcmp, "kcmp"),
+    (libc::SYS_personality, "personality"),
+    (libc::SYS_unshare, "unshare"),
+    (libc::SYS_chroot, "chroot"),
+    (libc::SYS_mknodat, "mknodat"),
+];
+
+/// A named seccomp-bpf profile. Blocked syscalls fail with EPERM and are
+/// reported to the server through a user-notification listener.
+pub struct Profile {
+    pub name: &'static str,
+    blocked: Vec<(libc::c_long, &'static str)>,
+    unix_sockets_only: bool,
+    filter: Vec<libc::sock_filter>,
+}
+
+impl Profile {
+    /// Looks up a profile by name; `None` for "none" and unknown names alike,
+    /// so callers check `PROFILES` first.
+    pub fn named(name: &str) -> Option<Self> {
+        let (name, extra, unix_only): (&'static str, &[_], bool) = match name {
+            "default" => ("default", &[], false),
+            "strict" => ("strict", BLOCK_STRICT, false),
+            "no-network" => ("no-network", &[], true),
+            _ => return None,
+        };
+        let blocked: Vec<_> = BLOCK_DEFAULT.iter().chain(extra).copied().collect();
+        let filter = build_filter(&blocked, unix_only);
+        Some(Self { name, blocked, unix_sockets_only: unix_only, filter })
+    }
+
+    fn syscall_name(&self, nr: libc::c_int) -> &'static str {
+        if self.unix_sockets_only && nr as libc::c_long == libc::SYS_socket {
+            return "socket";
+        }
+        self.blocked.iter()
+            .find(|(n, _)| *n == nr as libc::c_long)
+            .map(|(_, s)| *s)
+            .unwrap_or("unknown")
+    }
+}
+
+fn stmt(code: u32, k: u32) -> libc::sock_filter {
+    libc::sock_filter { code: code as u16, jt: 0, jf: 0, k }
+}
+
+fn jump(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter { code: code as u16, jt, jf, k }
+}
+
+fn build_filter(blocked: &[(libc::c_long, &str)], unix_only: bool) -> Vec<libc::sock_filter> {
+    use libc::{BPF_ABS, BPF_JEQ, BPF_JGE, BPF_JMP, BPF_K, BPF_LD, BPF_RET, BPF_W};
+    let notify = libc::SECCOMP_RET_USER_NOTIF;
+    let mut f = vec![
+        stmt(BPF_LD | BPF_W | BPF_ABS, SECCOMP_DATA_ARCH),
+        jump(BPF_JMP | BPF_JEQ | BPF_K, AUDIT_ARCH, 1, 0),
+        stmt(BPF_RET | BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+        stmt(BPF_LD | BPF_W | BPF_ABS, SECCOMP_DATA_NR),
+    ];
+    if cfg!(target_arch = "x86_64") {
+        f.push(jump(BPF_JMP | BPF_JGE | BPF_K, X32_SYSCALL_BIT, 0, 1));
+        f.push(stmt(BPF_RET | BPF_K, libc::SECCOMP_RET_KILL_PROCESS));
+    }
+    for (nr, _) in blocked {
+        f.push(jump(BPF_JMP | BPF_JEQ | BPF_K, *nr as u32, 0, 1));
+        f.push(stmt(BPF_RET | BPF_K, notify));
+    }
+    if unix_only {
+        f.push(jump(BPF_JMP | BPF_JEQ | BPF_K, libc::SYS_socket as u32, 0, 3));
+        f.push(stmt(BPF_LD | BPF_W | BPF_ABS, SECCOMP_DATA_ARG0));
+        f.push(jump(BPF_JMP | BPF_JEQ | BPF_K, libc::AF_UNIX as u32, 1, 0));
+        f.push(stmt(BPF_RET | BPF_K, notify));
+    }
+    f.push(stmt(BPF_RET | BPF_K, libc::SECCOMP_RET_ALLOW));
+    f
+}
+
+/// Child half: installs the filter and hands the listener fd to the server.
+pub struct SeccompChild {
+    profile: Arc<Profile>,
+    sock: OwnedFd,
+}
+
+/// Server half: receives the listener fd and answers notifications.
+pub struct SeccompListener {
+    profile: Arc<Profile>,
+    sock: OwnedFd,
+}
+
+pub fn pair(profile: Arc<Profile>) -> io::Result<(SeccompChild, SeccompListener)> {
+    let (a, b) = UnixStream::pair()?;
+    Ok((
+        SeccompChild { profile: profile.clone(), sock: a.into() },
+        SeccompListener { profile, sock: b.into() },
+    ))
+}
+
+impl SeccompChild {
+    /// # Safety
+    /// Must only be called in a freshly forked child, as the last step before exec.
+    pub unsafe fn install(&self) -> io::Result<()> {
+        if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let prog = libc::sock_fprog {
+            len: self.profile.filter.len() as u16,
+            filter: self.profile.filter.as_ptr() as *mut _,
+        };
+        let fd = libc::syscall(
+            libc::SYS_seccomp,
+            libc::SECCOMP_SET_MODE_FILTER,
+            libc::SECCOMP_FILTER_FLAG_NEW_LISTENER,
+            &prog as *const libc::sock_fprog,
+        );
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let res = send_fd(self.sock.as_raw_fd(), fd as RawFd);
+        libc::close(fd as RawFd);
+        res
+    }
+}
+
+impl SeccompListener {
+    /// Waits for the child's listener fd and answers every blocked syscall
+    /// with EPERM, reporting each one through `on_block`.
+    pub fn spawn(self, on_block: impl Fn(String) + Send + 'static) {
+        tokio::spawn(async move {
+            let profile = self.profile.clone();
+            let fd = match tokio::task::spawn_blocking(move || recv_fd(self.sock.as_raw_fd())).await {
+                Ok(Ok(Some(fd))) => fd,
+                Ok(Ok(None)) => return, // child exited before installing the filter
+                Ok(Err(e)) => { tracing::warn!("seccomp listener: {e}"); return; }
+                Err(_) => return,
+            };
+            // SAFETY: fd was just received and is owned by `fd`
+            let afd = match unsafe { AsyncFd::register(fd) } {
+                Ok(a) => a,
+                Err(e) => { tracing::warn!("seccomp listener: {e}"); return; }
+            };
+            while let Ok(mut guard) = afd.readable().await {
+                let res = guard.try_io(|inner| unsafe { answer(inner.as_raw_fd()) });
+                match res {
+                    Ok(Ok(nr)) => on_block(format!("seccomp:{}:{}", profile.name, profile.syscall_name(nr))),
+                    Ok(Err(e)) if e.raw_os_error() == Some(libc::ENOENT) => continue, // target died mid-call
+                    Ok(Err(_)) => break, // no tasks left in the filter
+                    Err(_would_block) => continue,
+                }
+            }
+        });
+    }
+}
+
+/// Receives one notification and fails the syscall with EPERM. The RECV
+/// ioctl ignores O_NONBLOCK, so poll first rather than block a runtime thread.
+unsafe fn answer(fd: RawFd) -> io::Result<libc::c_int> {
+    let mut pfd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+    if libc::poll(&mut pfd, 1, 0) < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if pfd.revents & libc::POLLIN == 0 {
+        if pfd.revents & libc::POLLHUP != 0 {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        return Err(io::ErrorKind::WouldBlock.into());
+    }
+    let mut req: libc::seccomp_notif = std::mem::zeroed();
+    if libc::ioctl(fd, libc::SECCOMP_IOCTL_NOTIF_RECV, &mut req) != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut resp = libc::seccomp_notif_resp { id: req.id, val: 0, error: -libc::EPERM, flags: 0 };
+    if libc::ioctl(fd, libc::SECCOMP_IOCTL_NOTIF_SEND, &mut resp) != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(req.data.nr)
+}
+
+/// cmsg buffer large enough for one fd, 8-byte aligned.
+type CmsgBuf = [u64; 4];
+
+unsafe fn send_fd(sock: RawFd, fd: RawFd) -> io::Result<()> {
+    let mut byte = [0u8; 1];
+    let mut iov = libc::iovec { iov_base: byte.as_mut_ptr().cast(), iov_len: 1 };
+    let mut buf: CmsgBuf = [0; 4];
+    let mut msg: libc::msghdr = std::mem::zeroed();
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = buf.as_mut_ptr().cast();
+    msg.msg_controllen = libc::CMSG_SPACE(std::mem::size_of::<RawFd>() as u32) as _;
+    let cmsg = libc::CMSG_FIRSTHDR(&msg);
+    (*cmsg).cmsg_level = libc::SOL_SOCKET;
+    (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+    (*cmsg).cmsg_len = libc::CMSG_LEN(std::mem::size_of::<RawFd>() as u32) as _;
+    std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut RawFd, fd);
+    if libc::sendmsg(sock, &msg, 0) < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn recv_fd(sock: RawFd) -> io::Result<Option<OwnedFd>> {
+    unsafe {
+        let mut byte = [0u8; 1];
+        let mut iov = libc::iovec { iov_base: byte.as_mut_ptr().cast(), iov_len: 1 };
+        let mut buf: CmsgBuf = [0; 4];
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = buf.as_mut_ptr().cast();
+        msg.msg_controllen = std::mem::size_of::<CmsgBuf>() as _;
+        let n = libc::recvmsg(sock, &mut msg, libc::MSG_CMSG_CLOEXEC);
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        if n == 0 || cmsg.is_null() || (*cmsg).cmsg_type != libc::SCM_RIGHTS {
+            return Ok(None);
+        }
+        let fd = std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const RawFd);
+        Ok(Some(OwnedFd::from_raw_fd(fd)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs a filter from `build_filter` on one syscall; supports only the
+    /// instructions it emits.
+    fn verdict(filter: &[libc::sock_filter], nr: libc::c_long, arg0: u64) -> u32 {
+        use libc::{BPF_ABS, BPF_JEQ, BPF_JGE, BPF_JMP, BPF_K, BPF_LD, BPF_RET, BPF_W};
+        let (mut acc, mut pc) = (0u32, 0usize);
+        loop {
+            let ins = filter[pc];
+            pc += 1;
+            match ins.code as u32 {
+                c if c == BPF_LD | BPF_W | BPF_ABS => {
+                    acc = match ins.k {
+                        SECCOMP_DATA_NR => nr as u32,
+                        SECCOMP_DATA_ARCH => AUDIT_ARCH,
+                        SECCOMP_DATA_ARG0 => arg0 as u32,
+                        k => panic!("load of offset {k}"),
+                    }
+                }
+                c if c == BPF_JMP | BPF_JEQ | BPF_K || c == BPF_JMP | BPF_JGE | BPF_K => {
+                    let hit = if c & 0xf0 == BPF_JEQ { acc == ins.k } else { acc >= ins.k };
+                    pc += if hit { ins.jt } else { ins.jf } as usize;
+                }
+                c if c == BPF_RET | BPF_K => return ins.k,
+                c => panic!("unexpected instruction {c:#x}"),
+            }
+        }
+    }
+
+    #[test]
+    fn io_uring_is_blocked_in_every_profile() {
+        for name in ["default", "strict", "no-network"] {
+            let p = Profile::named(name).unwrap();
+            for nr in [libc::SYS_io_uring_setup, libc::SYS_io_uring_enter, libc::SYS_io_uring_register] {
+                assert_eq!(verdict(&p.filter, nr, 0), libc::SECCOMP_RET_USER_NOTIF, "{name}: {}", p.syscall_name(nr as _));
+            }
+            assert_eq!(verdict(&p.filter, libc::SYS_read, 0), libc::SECCOMP_RET_ALLOW);
+        }
+    }
+
+    #[test]
+    fn no_network_allows_only_unix_sockets() {
+        let p = Profile::named("no-network").unwrap();
+        assert_eq!(verdict(&p.filter, libc::SYS_socket, libc::AF_UNIX as u64), libc::SECCOMP_RET_ALLOW);
+        assert_eq!(verdict(&p.filter, libc::SYS_socket, libc::AF_INET as u64), libc::SECCOMP_RET_USER_NOTIF);
+        let d = Profile::named("default").unwrap();
+        assert_eq!(verdict(&d.filter, libc::SYS_socket, libc::AF_INET as u64), libc::SECCOMP_RET_ALLOW);
+    }
+}
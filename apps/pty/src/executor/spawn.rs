@@ -1,105 +1,119 @@
 use std::collections::VecDeque;
 use std::sync::Arc;
+use std::os::unix::process::ExitStatusExt;
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+use nix::sys::signal::{killpg, Signal};
+use nix::unistd::Pid;
 use parking_lot::Mutex;
-use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, BufReader};
 use tokio::process::Command;
-use crate::models::StreamFrame;
+use tokio::sync::Notify;
+use crate::executor::exit_event;
+use crate::executor::isolation::Isolation;
+use crate::io::encoding::{Encoding, OutputDecoder};
+use crate::io::log::FrameLog;
+use crate::models::{JobSpec, StreamFrame};
 use crate::state::JobHandle;
 
 const BACKLOG_CAP: usize = 1024;
+/// Largest stdout/stderr frame, in bytes read.
+const CHUNK: usize = 4096;
 
-pub async fn spawn_noninteractive(cmd: Vec<String>, cwd: Option<String>) -> JobHandle {
+/// Deadline for a job: SIGTERM to its process group after `after`, SIGKILL
+/// to whatever is left `grace` later. Cancelling a job uses the same grace.
+#[derive(Clone, Copy, Debug)]
+pub struct Timeout {
+    pub after: Option<Duration>,
+    pub grace: Duration,
+}
+
+pub async fn spawn_noninteractive(
+    spec: JobSpec,
+    mut iso: Isolation,
+    timeout: Timeout,
+    enc: Encoding,
+    log: Option<Arc<FrameLog>>,
+) -> anyhow::Result<JobHandle> {
     // Build command
+    let cmd = &spec.cmd;
     let joined = if cmd.len() == 1 { cmd[0].clone() } else { cmd.join(" ") };
 
     let mut c = Command::new("/bin/bash");
     c.arg("-lc").arg(joined.clone());
-    if let Some(dir) = cwd { c.current_dir(dir); }
+    if let Some(dir) = &spec.cwd { c.current_dir(dir); }
     c.stdout(std::process::Stdio::piped());
     c.stderr(std::process::Stdio::piped());
     c.env("TERM", "xterm");
+    c.process_group(0);
+    let cgroup = iso.cgroup.clone();
+    let seccomp_listener = iso.seccomp_listener.take();
+    // SAFETY: Isolation::enter and seal only issue async-signal-safe syscalls.
+    // std has already switched directory when pre_exec hooks run.
+    unsafe {
+        c.pre_exec(move || {
+            iso.enter()?;
+            iso.seal()
+        });
+    }
 
-    let child = Arc::new(tokio::sync::Mutex::new(c.spawn().expect("spawn failed")));
+    let child = Arc::new(tokio::sync::Mutex::new(c.spawn()?));
+    drop(c); // releases our copy of the child's seccomp socket
+    let pid = child.lock().await.id().map(|p| p as i32).unwrap_or_default();
     let child_watcher = Arc::clone(&child);
+    let cancel = Arc::new(Notify::new());
 
     // Shared state
     let (tx, _rx) = tokio::sync::broadcast::channel::<StreamFrame>(BACKLOG_CAP);
     let latest_seq = Arc::new(AtomicU64::new(0));
     let exit_code  = Arc::new(Mutex::new(None::<i32>));
+    let exit_signal = Arc::new(Mutex::new(None::<i32>));
+    let finished_at = Arc::new(Mutex::new(None::<Instant>));
     let backlog    = Arc::new(Mutex::new(VecDeque::with_capacity(BACKLOG_CAP)));
+    let started    = Instant::now();
+    let created_at = SystemTime::now();
+    let bytes      = Arc::new(AtomicU64::new(0));
 
-    // Helper for broadcasting frames
-    let push = {
+    // Helpers for broadcasting frames; `push` is for text and events
+    let emit = {
         let latest_seq = Arc::clone(&latest_seq);
         let backlog    = Arc::clone(&backlog);
         let tx         = tx.clone();
-        move |t: &str, data: String| {
+        let log        = log.clone();
+        move |t: &str, data: String, enc: Option<String>| {
+            // seq, log, backlog and channel order must agree, so all happen under one lock
+            let mut b = backlog.lock();
             let seq = latest_seq.fetch_add(1, Ordering::Relaxed) + 1;
-            let frame = StreamFrame { t: t.into(), seq, d: data };
-            {
-                let mut b = backlog.lock();
-                if b.len() == b.capacity() { b.pop_front(); }
-                b.push_back(frame.clone());
-            }
+            let ts_ms = started.elapsed().as_millis() as u64;
+            let frame = StreamFrame { t: t.into(), seq, d: data, enc, ts_ms };
+            if let Some(log) = &log { log.append(&frame); }
+            if b.len() == b.capacity() { b.pop_front(); }
+            b.push_back(frame.clone());
             let _ = tx.send(frame);
         }
     };
+    let push = {
+        let emit = emit.clone();
+        move |t: &str, data: String| emit(t, data, None)
+    };
 
     push("event", "stream-start".into());
 
+    if let Some(listener) = seccomp_listener {
+        let push = push.clone();
+        listener.spawn(move |ev| push("event", ev));
+    }
+
     // Spawn readers and keep their JoinHandles
     let stdout_task = if let Some(out) = child_watcher.lock().await.stdout.take() {
-        let push = push.clone();
-        Some(tokio::spawn(async move {
-            let mut reader = BufReader::new(out);
-            let mut buf = Vec::with_capacity(256);
-            loop {
-                buf.clear();
-                match reader.read_until(b'\n', &mut buf).await {
-                    Ok(0) => {
-                        if !buf.is_empty() {
-                            push("stdout", String::from_utf8_lossy(&buf).into());
-                        }
-                        break;
-                    }
-                    Ok(_) => push("stdout", String::from_utf8_lossy(&buf).into()),
-                    Err(e) => {
-                        push("event", format!("stdout-reader-error:{e}"));
-                        break;
-                    }
-                }
-            }
-            push("event", "stdout-reader-done".into());
-        }))
+        Some(tokio::spawn(pump(out, "stdout", enc, bytes.clone(), emit.clone(), push.clone())))
     } else {
         push("event", "stdout-none".into());
         None
     };
 
     let stderr_task = if let Some(err) = child_watcher.lock().await.stderr.take() {
-        let push = push.clone();
-        Some(tokio::spawn(async move {
-            let mut reader = BufReader::new(err);
-            let mut buf = Vec::with_capacity(256);
-            loop {
-                buf.clear();
-                match reader.read_until(b'\n', &mut buf).await {
-                    Ok(0) => {
-                        if !buf.is_empty() {
-                            push("stderr", String::from_utf8_lossy(&buf).into());
-                        }
-                        break;
-                    }
-                    Ok(_) => push("stderr", String::from_utf8_lossy(&buf).into()),
-                    Err(e) => {
-                        push("event", format!("stderr-reader-error:{e}"));
-                        break;
-                    }
-                }
-            }
-            push("event", "stderr-reader-done".into());
-        }))
+        Some(tokio::spawn(pump(err, "stderr", enc, bytes.clone(), emit.clone(), push.clone())))
     } else {
         push("event", "stderr-none".into());
         None
@@ -108,13 +122,50 @@ pub async fn spawn_noninteractive(cmd: Vec<String>, cwd: Option<String>) -> JobH
     // Wait for process, then readers, then exit
     {
         let exit_code = Arc::clone(&exit_code);
+        let exit_signal = Arc::clone(&exit_signal);
+        let finished_at = Arc::clone(&finished_at);
         let push = push.clone();
+        let cgroup = cgroup.clone();
+        let cancel = Arc::clone(&cancel);
+        let log = log.clone();
         tokio::spawn(async move {
-            let code = match child_watcher.lock().await.wait().await {
-                Ok(status) => status.code(),
+            let mut child = child_watcher.lock().await;
+            let deadline = async {
+                match timeout.after {
+                    Some(after) => tokio::time::sleep(after).await,
+                    None => std::future::pending().await,
+                }
+            };
+            let reason = tokio::select! {
+                r = child.wait() => Err(r),
+                _ = deadline => Ok("timeout"),
+                _ = cancel.notified() => Ok("cancelled"),
+            };
+            let status = match reason {
+                Err(r) => r,
+                Ok(reason) => {
+                    push("event", reason.into());
+                    let pgid = Pid::from_raw(pid);
+                    let _ = killpg(pgid, Signal::SIGTERM);
+                    let r = tokio::time::timeout(timeout.grace, child.wait()).await;
+                    // anything still in the group after the grace period gets SIGKILL
+                    if killpg(pgid, Signal::SIGKILL).is_ok() {
+                        push("event", "killed:SIGKILL".into());
+                    } else {
+                        push("event", "killed:SIGTERM".into());
+                    }
+                    match r {
+                        Ok(r) => r,
+                        Err(_) => child.wait().await,
+                    }
+                }
+            };
+            drop(child);
+            let (code, signal) = match status {
+                Ok(status) => (status.code(), status.signal()),
                 Err(e) => {
                     push("event", format!("wait-error:{e}"));
-                    None
+                    (None, None)
                 }
             };
 
@@ -123,10 +174,82 @@ pub async fn spawn_noninteractive(cmd: Vec<String>, cwd: Option<String>) -> JobH
             if let Some(t) = stderr_task { let _ = t.await; }
 
             *exit_code.lock() = code;
-            push("event", format!("exit:{code:?}"));
+            *exit_signal.lock() = signal;
+            *finished_at.lock() = Some(Instant::now());
+            push("event", exit_event(code, signal, cgroup.as_deref()));
+            if let Some(log) = log { log.finish(); }
         });
     }
 
     tokio::task::yield_now().await;
-    JobHandle { latest_seq, tx, exit_code, backlog, child }
+    Ok(JobHandle {
+        latest_seq, tx, exit_code, exit_signal, backlog, child, pid, cgroup, finished_at, cancel, log,
+        spec: Arc::new(spec), started, created_at, bytes,
+    })
+}
+
+/// Forwards one output pipe as `t` frames, a line (or `CHUNK` bytes) at a time.
+async fn pump(
+    pipe: impl AsyncRead + Unpin,
+    t: &'static str,
+    enc: Encoding,
+    bytes: Arc<AtomicU64>,
+    emit: impl Fn(&str, String, Option<String>),
+    push: impl Fn(&str, String),
+) {
+    let mut reader = BufReader::new(pipe);
+    let mut dec = OutputDecoder::new(enc);
+    let mut buf = Vec::with_capacity(CHUNK);
+    loop {
+        buf.clear();
+        match read_chunk(&mut reader, &mut buf).await {
+            Ok(0) => break,
+            Ok(n) => {
+                bytes.fetch_add(n as u64, Ordering::Relaxed);
+                if let Some(d) = dec.decode(&buf) {
+                    emit(t, d, dec.tag());
+                }
+            }
+            Err(e) => {
+                push("event", format!("{t}-reader-error:{e}"));
+                break;
+            }
+        }
+    }
+    if let Some(d) = dec.finish() {
+        emit(t, d, dec.tag());
+    }
+    push("event", format!("{t}-reader-done"));
+}
+
+/// Reads up to and including the next newline, but never more than `CHUNK`
+/// bytes, so output without newlines can't grow a frame without bound.
+async fn read_chunk<R: AsyncBufRead + Unpin>(r: &mut R, buf: &mut Vec<u8>) -> std::io::Result<usize> {
+    loop {
+        let avail = r.fill_buf().await?;
+        if avail.is_empty() {
+            return Ok(buf.len());
+        }
+        let avail = &avail[..avail.len().min(CHUNK - buf.len())];
+        let (n, line_end) = match avail.iter().position(|&b| b == b'\n') {
+            Some(i) => (i + 1, true),
+            None => (avail.len(), false),
+        };
+        buf.extend_from_slice(&avail[..n]);
+        r.consume(n);
+        if line_end || buf.len() >= CHUNK {
+            return Ok(buf.len());
+        }
+    }
+}
+
+/// Sends `sig` to the job's shell, or to its whole process group.
+pub fn signal_job(h: &JobHandle, sig: Signal, group: bool) -> nix::Result<()> {
+    let pid = Pid::from_raw(h.pid);
+    if group { killpg(pid, sig) } else { nix::sys::signal::kill(pid, sig) }
+}
+
+/// SIGTERM to the job's process group, SIGKILL after the grace period.
+pub fn cancel_job(h: &JobHandle) {
+    h.cancel.notify_one();
 }
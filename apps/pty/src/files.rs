@@ -0,0 +1,174 @@
+use nix::errno::Errno;
+use nix::fcntl::{open, openat2, OFlag, OpenHow, ResolveFlag};
+use nix::sys::stat::{mkdirat, Mode};
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::{Component, Path, PathBuf};
+
+#[derive(Debug, thiserror::Error)]
+pub enum PathError {
+    #[error("invalid path: {0}")]
+    Invalid(&'static str),
+    #[error("path escapes the sandbox root")]
+    Escape,
+    #[error("not found")]
+    NotFound,
+    #[error("io: {0}")]
+    Io(std::io::Error),
+}
+
+impl From<Errno> for PathError {
+    fn from(e: Errno) -> Self {
+        match e {
+            // openat2 reports a component leaving the root as EXDEV; magic links as ELOOP
+            Errno::EXDEV | Errno::ELOOP => PathError::Escape,
+            Errno::ENOENT => PathError::NotFound,
+            Errno::ENOTDIR => PathError::Invalid("not a directory"),
+            Errno::EISDIR => PathError::Invalid("is a directory"),
+            e => PathError::Io(e.into()),
+        }
+    }
+}
+
+impl From<std::io::Error> for PathError {
+    fn from(e: std::io::Error) -> Self {
+        match e.raw_os_error() {
+            Some(n) => Errno::from_raw(n).into(),
+            None => PathError::Io(e),
+        }
+    }
+}
+
+/// Directory the /sandbox file routes are confined to. Every path is opened
+/// with `openat2(RESOLVE_BENEATH)` relative to the root, so `..` and symlinks
+/// can't leave it even if the tree changes underneath a request.
+#[derive(Clone, Debug)]
+pub struct SandboxRoot {
+    root: PathBuf,
+}
+
+impl SandboxRoot {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.root
+    }
+
+    /// Validates a request path and returns it relative to the root.
+    pub fn relative(&self, path: &str) -> Result<PathBuf, PathError> {
+        if path.contains('\0') {
+            return Err(PathError::Invalid("contains NUL"));
+        }
+        let mut rel = PathBuf::new();
+        for c in Path::new(path).components() {
+            match c {
+                Component::Normal(part) => rel.push(part),
+                Component::RootDir | Component::CurDir => {}
+                Component::ParentDir => return Err(PathError::Invalid("'..' is not allowed")),
+                Component::Prefix(_) => return Err(PathError::Invalid("unsupported prefix")),
+            }
+        }
+        if rel.as_os_str().is_empty() {
+            return Err(PathError::Invalid("empty path"));
+        }
+        Ok(rel)
+    }
+
+    pub async fn read(&self, path: &str) -> Result<String, PathError> {
+        let this = self.clone();
+        let rel = self.relative(path)?;
+        blocking(move || {
+            let root = this.open_root(false)?;
+            let fd = beneath(&root, &rel, OFlag::O_RDONLY, Mode::empty())?;
+            let mut s = String::new();
+            std::fs::File::from(fd).read_to_string(&mut s)?;
+            Ok(s)
+        })
+        .await
+    }
+
+    /// Like `read`, for files that needn't be text.
+    pub async fn read_bytes(&self, path: &str) -> Result<Vec<u8>, PathError> {
+        let this = self.clone();
+        let rel = self.relative(path)?;
+        blocking(move || {
+            let root = this.open_root(false)?;
+            let fd = beneath(&root, &rel, OFlag::O_RDONLY, Mode::empty())?;
+            let mut buf = Vec::new();
+            std::fs::File::from(fd).read_to_end(&mut buf)?;
+            Ok(buf)
+        })
+        .await
+    }
+
+    /// Writes `content`, creating parent directories one verified level at a time.
+    pub async fn write(&self, path: &str, content: String) -> Result<PathBuf, PathError> {
+        let this = self.clone();
+        let rel = self.relative(path)?;
+        blocking(move || {
+            let root = this.open_root(true)?;
+            let mut prefix = PathBuf::new();
+            if let Some(parent) = rel.parent() {
+                for part in parent.components() {
+                    let dir = if prefix.as_os_str().is_empty() {
+                        root.try_clone()?
+                    } else {
+                        beneath(&root, &prefix, OFlag::O_DIRECTORY, Mode::empty())?
+                    };
+                    match mkdirat(Some(dir.as_raw_fd()), part.as_os_str(), Mode::from_bits_truncate(0o755)) {
+                        Ok(()) | Err(Errno::EEXIST) => {}
+                        Err(e) => return Err(e.into()),
+                    }
+                    prefix.push(part);
+                }
+            }
+            let flags = OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_TRUNC;
+            let fd = beneath(&root, &rel, flags, Mode::from_bits_truncate(0o644))?;
+            std::fs::File::from(fd).write_all(content.as_bytes())?;
+            Ok(this.root.join(&rel))
+        })
+        .await
+    }
+
+    /// Opens a directory beneath the root ("" or "/" is the root itself),
+    /// for a session to `fchdir` into.
+    pub async fn open_dir(&self, path: &str) -> Result<OwnedFd, PathError> {
+        let this = self.clone();
+        let is_root = Path::new(path).components().all(|c| matches!(c, Component::RootDir | Component::CurDir));
+        let rel = if is_root { PathBuf::from(".") } else { self.relative(path)? };
+        blocking(move || {
+            let root = this.open_root(false)?;
+            beneath(&root, &rel, OFlag::O_PATH | OFlag::O_DIRECTORY, Mode::empty())
+        })
+        .await
+    }
+
+    fn open_root(&self, create: bool) -> Result<OwnedFd, PathError> {
+        if create {
+            std::fs::create_dir_all(&self.root)?;
+        }
+        let fd = open(&self.root, OFlag::O_DIRECTORY | OFlag::O_CLOEXEC, Mode::empty())?;
+        // SAFETY: fresh fd from open(2)
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+}
+
+fn beneath(root: &OwnedFd, rel: &Path, flags: OFlag, mode: Mode) -> Result<OwnedFd, PathError> {
+    let how = OpenHow::new()
+        .flags(flags | OFlag::O_CLOEXEC)
+        .mode(mode)
+        .resolve(ResolveFlag::RESOLVE_BENEATH | ResolveFlag::RESOLVE_NO_MAGICLINKS);
+    let fd = openat2(root.as_raw_fd(), rel, how)?;
+    // SAFETY: fresh fd from openat2(2)
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+async fn blocking<T: Send + 'static>(
+    f: impl FnOnce() -> Result<T, PathError> + Send + 'static,
+) -> Result<T, PathError> {
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| PathError::Io(std::io::Error::other(e)))?
+}
@@ -0,0 +1,65 @@
+use axum::response::Response;
+use bytes::Bytes;
+use futures_util::stream::{self, StreamExt};
+use serde_json::json;
+use std::time::UNIX_EPOCH;
+use crate::io::encoding::{Encoding, OutputDecoder};
+use crate::io::stream::FrameCursor;
+use crate::models::StreamFrame;
+use crate::state::SessionHandle;
+
+/// The session so far as an asciicast v2 file: output as "o" events, resize
+/// frames as "r" events and missing ranges as "m" markers. Ends at the frame
+/// that was latest when the request came in.
+pub fn recording(h: &SessionHandle) -> Response {
+    let end = *h.latest_seq.lock();
+    let (cols, rows) = h.initial_size;
+    let header = json!({
+        "version": 2,
+        "width": cols,
+        "height": rows,
+        "timestamp": h.created_at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+        "env": { "SHELL": h.shell },
+    });
+    let cursor = FrameCursor::new(h.backlog.clone(), h.log.clone(), h.tx.subscribe(), 0);
+    let rec = Recorder { dec: OutputDecoder::new(Encoding::Utf8), last_ms: 0 };
+    let events = stream::unfold((cursor, rec, end == 0), move |(mut cursor, mut rec, mut done)| async move {
+        while !done {
+            let f = cursor.next().await?;
+            done = f.seq >= end || f.is_exit();
+            if let Some(line) = rec.event(&f) {
+                return Some((line, (cursor, rec, done)));
+            }
+        }
+        None
+    });
+    let body = stream::once(async move { header.to_string() })
+        .chain(events)
+        .map(|line| Ok::<Bytes, std::convert::Infallible>(Bytes::from(line + "\n")));
+    Response::builder()
+        .status(200)
+        .header("content-type", "application/x-asciicast")
+        .body(axum::body::Body::from_stream(body))
+        .unwrap()
+}
+
+struct Recorder {
+    /// base64 frames are decoded back to text; a split sequence waits for the next one
+    dec: OutputDecoder,
+    last_ms: u64,
+}
+
+impl Recorder {
+    fn event(&mut self, f: &StreamFrame) -> Option<String> {
+        // seq-0 frames have no time of their own; keep the clock from going backwards
+        self.last_ms = self.last_ms.max(f.ts_ms);
+        let at = self.last_ms as f64 / 1000.0;
+        let (code, data) = match (f.t.as_str(), f.d.split_once(':')) {
+            ("stdout" | "stderr", _) => ("o", self.dec.frame_text(f)?),
+            ("event", Some(("resize", size))) => ("r", size.to_string()),
+            ("event", Some(("gap", _))) => ("m", f.d.clone()),
+            _ => return None,
+        };
+        Some(json!([at, code, data]).to_string())
+    }
+}
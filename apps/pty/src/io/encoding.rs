@@ -0,0 +1,157 @@
+use base64::Engine;
+use crate::models::StreamFrame;
+
+/// How process output is put into `StreamFrame::d`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// UTF-8 text; invalid bytes become U+FFFD
+    #[default]
+    Utf8,
+    /// raw bytes as base64, frames carry `"enc":"base64"`
+    Base64,
+}
+
+impl Encoding {
+    pub const NAMES: [&'static str; 2] = ["utf8", "base64"];
+
+    pub fn named(name: Option<&str>) -> Option<Self> {
+        match name.unwrap_or("utf8") {
+            "utf8" => Some(Encoding::Utf8),
+            "base64" => Some(Encoding::Base64),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "utf8",
+            Encoding::Base64 => "base64",
+        }
+    }
+
+    /// Value of the frame's `enc` field.
+    pub fn tag(self) -> Option<String> {
+        match self {
+            Encoding::Utf8 => None,
+            Encoding::Base64 => Some("base64".into()),
+        }
+    }
+}
+
+/// Turns successive reads into frame data. In UTF-8 mode a multibyte sequence
+/// split across reads is held back until the rest arrives.
+pub struct OutputDecoder {
+    enc: Encoding,
+    pending: Vec<u8>,
+}
+
+impl OutputDecoder {
+    pub fn new(enc: Encoding) -> Self {
+        Self { enc, pending: Vec::new() }
+    }
+
+    /// `None` when everything read so far is an incomplete sequence.
+    pub fn decode(&mut self, bytes: &[u8]) -> Option<String> {
+        if self.enc == Encoding::Base64 {
+            return (!bytes.is_empty()).then(|| base64::engine::general_purpose::STANDARD.encode(bytes));
+        }
+        self.pending.extend_from_slice(bytes);
+        let mut out = String::new();
+        let mut rest = &self.pending[..];
+        loop {
+            match std::str::from_utf8(rest) {
+                Ok(s) => {
+                    out.push_str(s);
+                    rest = &[];
+                    break;
+                }
+                Err(e) => {
+                    let (valid, after) = rest.split_at(e.valid_up_to());
+                    // SAFETY: from_utf8 just validated this prefix
+                    out.push_str(unsafe { std::str::from_utf8_unchecked(valid) });
+                    match e.error_len() {
+                        Some(n) => {
+                            out.push(char::REPLACEMENT_CHARACTER);
+                            rest = &after[n..];
+                        }
+                        // truncated sequence at the end: keep it for the next read
+                        None => {
+                            rest = after;
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        self.pending = rest.to_vec();
+        (!out.is_empty()).then_some(out)
+    }
+
+    /// Flushes a sequence left incomplete at EOF.
+    pub fn finish(&mut self) -> Option<String> {
+        let rest = std::mem::take(&mut self.pending);
+        (!rest.is_empty()).then(|| String::from_utf8_lossy(&rest).into_owned())
+    }
+
+    pub fn tag(&self) -> Option<String> {
+        self.enc.tag()
+    }
+
+    /// Text of a stdout/stderr frame; base64 frames are decoded back to
+    /// bytes and through this decoder (which should be in UTF-8 mode).
+    pub fn frame_text(&mut self, f: &StreamFrame) -> Option<String> {
+        if f.t != "stdout" && f.t != "stderr" {
+            return None;
+        }
+        match f.enc {
+            Some(_) => {
+                let raw = base64::engine::general_purpose::STANDARD.decode(&f.d).ok()?;
+                self.decode(&raw)
+            }
+            None => Some(f.d.clone()),
+        }
+    }
+}
+
+/// The largest char boundary of `s` at or below `i`.
+pub fn floor_char_boundary(s: &str, mut i: usize) -> usize {
+    while !s.is_char_boundary(i) {
+        i -= 1;
+    }
+    i
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn holds_back_a_split_multibyte_char() {
+        let mut dec = OutputDecoder::new(Encoding::Utf8);
+        let euro = "€".as_bytes();
+        assert_eq!(dec.decode(&[b'a', euro[0]]).as_deref(), Some("a"));
+        assert_eq!(dec.decode(&euro[1..2]), None);
+        assert_eq!(dec.decode(&[euro[2], b'b']).as_deref(), Some("€b"));
+        assert_eq!(dec.finish(), None);
+    }
+
+    #[test]
+    fn replaces_invalid_bytes_and_flushes_a_truncated_tail() {
+        let mut dec = OutputDecoder::new(Encoding::Utf8);
+        assert_eq!(dec.decode(b"x\xffy\xe2\x82").as_deref(), Some("x\u{fffd}y"));
+        assert_eq!(dec.finish().as_deref(), Some("\u{fffd}"));
+    }
+
+    #[test]
+    fn base64_passes_bytes_through_and_frame_text_decodes_them() {
+        let mut enc = OutputDecoder::new(Encoding::Base64);
+        let mut dec = OutputDecoder::new(Encoding::Utf8);
+        let euro = "€".as_bytes();
+        let frame = |d: String| StreamFrame { t: "stdout".into(), seq: 1, d, enc: Encoding::Base64.tag(), ts_ms: 0 };
+        let a = frame(enc.decode(&euro[..1]).unwrap());
+        let b = frame(enc.decode(&euro[1..]).unwrap());
+        assert_eq!(dec.frame_text(&a), None);
+        assert_eq!(dec.frame_text(&b).as_deref(), Some("€"));
+        assert_eq!(enc.decode(b""), None);
+    }
+}
@@ -0,0 +1,116 @@
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use crate::io::encoding::{floor_char_boundary, Encoding, OutputDecoder};
+use crate::io::stream::FrameCursor;
+use crate::models::ExpectResponse;
+
+/// Text searched when a new frame arrives: its own and up to this much before it.
+const WINDOW: usize = 16 << 10;
+
+/// Waits until `re` matches the output read from `cursor`, the job or
+/// session ends, or `timeout` passes.
+pub async fn expect(mut cursor: FrameCursor, re: &Regex, from: u64, timeout: Duration) -> ExpectResponse {
+    let mut win = Window::default();
+    let mut dec = OutputDecoder::new(Encoding::Utf8);
+    let mut resp = ExpectResponse { state: "timeout".into(), seq_scanned: from, ..Default::default() };
+    let deadline = tokio::time::sleep(timeout);
+    tokio::pin!(deadline);
+    loop {
+        let f = tokio::select! {
+            f = cursor.next() => f,
+            _ = &mut deadline => return resp,
+        };
+        let Some(f) = f.filter(|f| !f.is_exit()) else {
+            resp.state = "exited".into();
+            return resp;
+        };
+        if f.seq > 0 {
+            resp.seq_scanned = f.seq;
+        }
+        let Some(text) = dec.frame_text(&f) else { continue };
+        win.push(&text, f.seq);
+        let Some(caps) = re.captures(&win.text) else { continue };
+        let m = caps.get(0).unwrap();
+        resp.state = "matched".into();
+        resp.seq = Some(win.seq_at(m.end()));
+        resp.matched = Some(m.as_str().to_string());
+        resp.groups = caps.iter().skip(1).map(|g| g.map(|g| g.as_str().to_string())).collect();
+        resp.named = re
+            .capture_names()
+            .flatten()
+            .filter_map(|name| Some((name.to_string(), caps.name(name)?.as_str().to_string())))
+            .collect::<BTreeMap<_, _>>();
+        return resp;
+    }
+}
+
+/// The tail of the output, with the seq of the frame each part came from.
+#[derive(Default)]
+struct Window {
+    text: String,
+    /// (end offset in `text`, seq), one per frame still in the window
+    frames: Vec<(usize, u64)>,
+}
+
+impl Window {
+    fn push(&mut self, chunk: &str, seq: u64) {
+        let cut = floor_char_boundary(&self.text, self.text.len().saturating_sub(WINDOW));
+        if cut > 0 {
+            self.text.drain(..cut);
+            self.frames.retain_mut(|(end, _)| {
+                *end = end.saturating_sub(cut);
+                *end > 0
+            });
+        }
+        self.text.push_str(chunk);
+        self.frames.push((self.text.len(), seq));
+    }
+
+    /// Seq of the frame holding byte `offset - 1`.
+    fn seq_at(&self, offset: usize) -> u64 {
+        let i = self.frames.partition_point(|&(end, _)| end < offset);
+        self.frames.get(i).or(self.frames.last()).map_or(0, |&(_, seq)| seq)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seq_at_maps_offsets_to_frames() {
+        let mut win = Window::default();
+        win.push("abc", 4);
+        win.push("de", 5);
+        win.push("f", 7);
+        assert_eq!(win.seq_at(1), 4);
+        assert_eq!(win.seq_at(3), 4);
+        assert_eq!(win.seq_at(4), 5);
+        assert_eq!(win.seq_at(5), 5);
+        assert_eq!(win.seq_at(6), 7);
+        assert_eq!(win.seq_at(99), 7);
+    }
+
+    #[test]
+    fn a_match_across_frames_ends_in_the_later_one() {
+        let mut win = Window::default();
+        win.push("Continue? [", 1);
+        win.push("y/N] ", 2);
+        let m = Regex::new(r"Continue\? \[y/N\]").unwrap().find(&win.text).unwrap();
+        assert_eq!(win.seq_at(m.end()), 2);
+    }
+
+    #[test]
+    fn old_text_leaves_the_window_with_its_frames() {
+        let mut win = Window::default();
+        win.push(&"a".repeat(WINDOW), 1);
+        win.push(&"é".repeat(WINDOW / 2), 2);
+        win.push("z", 3);
+        assert!(win.text.len() <= 2 * WINDOW);
+        assert!(!win.text.starts_with('a'));
+        assert_eq!(win.frames.iter().map(|&(_, s)| s).collect::<Vec<_>>(), [2, 3]);
+        assert_eq!(win.seq_at(1), 2);
+        assert_eq!(win.seq_at(win.text.len()), 3);
+    }
+}
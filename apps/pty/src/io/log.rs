@@ -0,0 +1,218 @@
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Weak};
+use crate::config::LogConfig;
+use crate::models::StreamFrame;
+
+/// One index entry per this many frames; lookups scan forward from the entry.
+const INDEX_STRIDE: u64 = 64;
+
+/// Frames queued for the writer across all logs. A log whose frame doesn't
+/// fit stops growing, the same as one that hit its size limit.
+const QUEUE: usize = 16 << 10;
+
+enum Op {
+    Append(Arc<FrameLog>, StreamFrame),
+    Finish(Arc<FrameLog>),
+}
+
+/// Directory of per-id frame logs, with a budget on their combined size.
+/// Logs of finished jobs and sessions are deleted oldest-first to stay under it.
+/// All file writes happen on one writer thread, so emitting a frame never
+/// waits for the disk.
+pub struct LogStore {
+    dir: PathBuf,
+    max_file: u64,
+    max_total: u64,
+    total: AtomicU64,
+    finished: Mutex<VecDeque<Weak<FrameLog>>>,
+    queue: SyncSender<Op>,
+}
+
+impl LogStore {
+    /// Creates `dir` and clears logs left over from a previous run.
+    pub fn open(cfg: &LogConfig) -> anyhow::Result<Arc<Self>> {
+        std::fs::create_dir_all(&cfg.dir)?;
+        for entry in std::fs::read_dir(&cfg.dir)?.flatten() {
+            if entry.path().extension().is_some_and(|e| e == "ndjson") {
+                let _ = std::fs::remove_file(entry.path());
+            }
+        }
+        let mb = |v: u64| if v == 0 { u64::MAX } else { v << 20 };
+        let (queue, rx) = mpsc::sync_channel(QUEUE);
+        std::thread::Builder::new().name("frame-log".into()).spawn(move || writer(rx))?;
+        Ok(Arc::new(Self {
+            dir: cfg.dir.clone(),
+            max_file: mb(cfg.max_file_mb),
+            max_total: mb(cfg.max_total_mb),
+            total: AtomicU64::new(0),
+            finished: Mutex::new(VecDeque::new()),
+            queue,
+        }))
+    }
+
+    pub fn create(self: &Arc<Self>, id: &str) -> anyhow::Result<Arc<FrameLog>> {
+        let path = self.dir.join(format!("{id}.ndjson"));
+        let file = File::options().create_new(true).append(true).open(&path)?;
+        Ok(Arc::new(FrameLog {
+            path,
+            store: Arc::clone(self),
+            open: AtomicBool::new(true),
+            inner: Mutex::new(LogInner { file: Some(file), len: 0, first: 0, last: 0, index: Vec::new() }),
+        }))
+    }
+
+    /// Makes room for `n` more bytes by deleting finished logs; false if that isn't enough.
+    fn reserve(&self, n: u64) -> bool {
+        loop {
+            let fits = |t: u64| (t + n <= self.max_total).then_some(t + n);
+            if self.total.fetch_update(Ordering::Relaxed, Ordering::Relaxed, fits).is_ok() {
+                return true;
+            }
+            let Some(oldest) = self.finished.lock().pop_front() else { return false };
+            if let Some(log) = oldest.upgrade() {
+                tracing::info!("frame log budget exceeded, dropping {}", log.path.display());
+                log.discard();
+            }
+        }
+    }
+}
+
+fn writer(rx: Receiver<Op>) {
+    for op in rx {
+        match op {
+            Op::Append(log, f) => log.write(&f),
+            Op::Finish(log) => {
+                log.inner.lock().file = None;
+                log.store.finished.lock().push_back(Arc::downgrade(&log));
+            }
+        }
+    }
+}
+
+/// Append-only ndjson log of every frame of one job or session.
+pub struct FrameLog {
+    path: PathBuf,
+    store: Arc<LogStore>,
+    /// still taking frames; cleared when it fills up or finishes
+    open: AtomicBool,
+    inner: Mutex<LogInner>,
+}
+
+struct LogInner {
+    /// None once the log is full, finished, or discarded
+    file: Option<File>,
+    len: u64,
+    first: u64,
+    last: u64,
+    /// (seq, offset) of every `INDEX_STRIDE`th frame
+    index: Vec<(u64, u64)>,
+}
+
+impl FrameLog {
+    /// Queues one frame for the writer thread. Once the per-file or total
+    /// budget is spent the log stops growing, and later frames live only in
+    /// the memory backlog.
+    pub fn append(self: &Arc<Self>, f: &StreamFrame) {
+        if !self.open.load(Ordering::Relaxed) {
+            return;
+        }
+        if let Err(TrySendError::Full(_)) = self.store.queue.try_send(Op::Append(Arc::clone(self), f.clone())) {
+            tracing::warn!("frame log {}: writer behind, stopping at seq {}", self.path.display(), f.seq);
+            self.open.store(false, Ordering::Relaxed);
+        }
+    }
+
+    fn write(&self, f: &StreamFrame) {
+        let mut guard = self.inner.lock();
+        let inner = &mut *guard;
+        let Some(file) = inner.file.as_mut() else { return };
+        let line = serde_json::to_string(f).unwrap() + "\n";
+        let n = line.len() as u64;
+        if inner.len + n > self.store.max_file || !self.store.reserve(n) {
+            tracing::info!("frame log {} full at seq {}", self.path.display(), inner.last);
+            self.open.store(false, Ordering::Relaxed);
+            inner.file = None;
+            return;
+        }
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            tracing::warn!("frame log {}: {e}", self.path.display());
+            self.store.total.fetch_sub(n, Ordering::Relaxed);
+            self.open.store(false, Ordering::Relaxed);
+            inner.file = None;
+            return;
+        }
+        if inner.first == 0 {
+            inner.first = f.seq;
+        }
+        match inner.index.last() {
+            Some(&(seq, _)) if f.seq < seq + INDEX_STRIDE => {}
+            _ => inner.index.push((f.seq, inner.len)),
+        }
+        inner.len += n;
+        inner.last = f.seq;
+    }
+
+    /// Called after the exit frame. Once the writer has caught up the file
+    /// is closed and the log becomes eligible for eviction.
+    pub fn finish(self: &Arc<Self>) {
+        self.open.store(false, Ordering::Relaxed);
+        if let Err(TrySendError::Full(op)) = self.store.queue.try_send(Op::Finish(Arc::clone(self))) {
+            let queue = self.store.queue.clone();
+            tokio::task::spawn_blocking(move || queue.send(op));
+        }
+    }
+
+    /// Up to `max` frames with `from <= seq <= to`, in order. Stops early
+    /// where the log doesn't reach.
+    pub fn read(&self, from: u64, to: u64, max: usize) -> Vec<StreamFrame> {
+        let (offset, end) = {
+            let inner = self.inner.lock();
+            if inner.first == 0 || from > inner.last || from < inner.first {
+                return Vec::new();
+            }
+            let i = inner.index.partition_point(|&(seq, _)| seq <= from);
+            (inner.index[i - 1].1, inner.len)
+        };
+        let read = || -> std::io::Result<Vec<StreamFrame>> {
+            let mut file = File::open(&self.path)?;
+            file.seek(SeekFrom::Start(offset))?;
+            let mut out = Vec::new();
+            for line in BufReader::new(file.take(end - offset)).lines() {
+                let Ok(f) = serde_json::from_str::<StreamFrame>(&line?) else { break };
+                if f.seq > to || out.len() == max {
+                    break;
+                }
+                if f.seq >= from {
+                    out.push(f);
+                }
+            }
+            Ok(out)
+        };
+        read().unwrap_or_else(|e| {
+            tracing::warn!("frame log {}: {e}", self.path.display());
+            Vec::new()
+        })
+    }
+
+    /// Deletes the file; reads return nothing afterwards.
+    fn discard(&self) {
+        self.open.store(false, Ordering::Relaxed);
+        let mut inner = self.inner.lock();
+        let _ = std::fs::remove_file(&self.path);
+        self.store.total.fetch_sub(inner.len, Ordering::Relaxed);
+        *inner = LogInner { file: None, len: 0, first: 0, last: 0, index: Vec::new() };
+    }
+}
+
+// The log lives as long as its job or session handle (and any open streams).
+impl Drop for FrameLog {
+    fn drop(&mut self) {
+        self.discard();
+    }
+}
@@ -1 +1,7 @@
+pub mod cast;
+pub mod encoding;
+pub mod expect;
+pub mod log;
+pub mod screen;
 pub mod stream;
+pub mod ws;
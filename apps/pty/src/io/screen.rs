@@ -0,0 +1,57 @@
+use crate::models::{CellAttrs, CursorPosition, ScreenResponse, ScreenSpan};
+
+/// The emulator's visible grid; `attrs` adds per-row attribute runs.
+pub fn snapshot(parser: &vt100::Parser, seq: u64, attrs: bool) -> ScreenResponse {
+    let screen = parser.screen();
+    let (rows, cols) = screen.size();
+    let (row, col) = screen.cursor_position();
+    ScreenResponse {
+        cols,
+        rows,
+        lines: screen.rows(0, cols).map(|l| l.trim_end().to_string()).collect(),
+        cursor: CursorPosition { row, col },
+        cursor_visible: !screen.hide_cursor(),
+        alternate_screen: screen.alternate_screen(),
+        seq,
+        attrs: attrs.then(|| (0..rows).map(|r| spans(screen, r, cols)).collect()),
+    }
+}
+
+fn spans(screen: &vt100::Screen, row: u16, cols: u16) -> Vec<ScreenSpan> {
+    let mut out: Vec<ScreenSpan> = Vec::new();
+    for col in 0..cols {
+        let Some(cell) = screen.cell(row, col) else { continue };
+        if cell.is_wide_continuation() {
+            continue;
+        }
+        let text = if cell.has_contents() { cell.contents() } else { " " };
+        let attrs = CellAttrs {
+            fg: color(cell.fgcolor()),
+            bg: color(cell.bgcolor()),
+            bold: cell.bold(),
+            italic: cell.italic(),
+            underline: cell.underline(),
+            inverse: cell.inverse(),
+        };
+        match out.last_mut() {
+            Some(last) if last.attrs == attrs => last.text.push_str(text),
+            _ => out.push(ScreenSpan { col, text: text.to_string(), attrs }),
+        }
+    }
+    // trailing blank cells with default attributes say nothing
+    if let Some(last) = out.last_mut().filter(|s| s.attrs == CellAttrs::default()) {
+        last.text.truncate(last.text.trim_end().len());
+        if last.text.is_empty() {
+            out.pop();
+        }
+    }
+    out
+}
+
+fn color(c: vt100::Color) -> Option<String> {
+    match c {
+        vt100::Color::Default => None,
+        vt100::Color::Idx(i) => Some(i.to_string()),
+        vt100::Color::Rgb(r, g, b) => Some(format!("#{r:02x}{g:02x}{b:02x}")),
+    }
+}
@@ -1,53 +1,253 @@
-use axum::response::IntoResponse;
-use futures_util::stream::{self, StreamExt};
+use axum::http::{header, HeaderMap};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use futures_util::stream::{self, Stream, StreamExt};
 use tokio::sync::broadcast;
 use bytes::Bytes;
-use serde_json::json;
-use crate::models::StreamFrame;
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use crate::io::log::FrameLog;
+use crate::models::{NodeEvent, StreamFrame};
+use crate::state::events::EventBus;
+use serde::Serialize;
 
-pub fn ndjson_stream_with_backlog(
-    mut backlog: Vec<StreamFrame>,
+/// Picks the body format from the Accept header (see `Format`). For SSE a
+/// Last-Event-ID header takes precedence over `from`.
+pub fn stream_with_backlog(
+    headers: &HeaderMap,
+    backlog: Backlog,
+    log: Option<Arc<FrameLog>>,
     rx: broadcast::Receiver<StreamFrame>,
     from: u64,
-) -> impl IntoResponse {
-    backlog.sort_by_key(|f| f.seq);
+) -> Response {
+    let format = Format::accepted(headers);
+    let from = match format {
+        Format::Sse => last_event_id(headers).unwrap_or(from),
+        _ => from,
+    };
+    format.respond(frames(FrameCursor::new(backlog, log, rx, from)))
+}
 
-    let banner = stream::once(async {
-        let s = json!({"t":"event","seq":0,"d":"stream-start"}).to_string() + "\n";
-        Ok::<Bytes, std::convert::Infallible>(Bytes::from(s))
-    });
+pub type Backlog = Arc<Mutex<VecDeque<StreamFrame>>>;
 
-    let past = backlog
-        .into_iter()
-        .filter(move |f| f.seq > from)
-        .map(|f| {
-            let line = serde_json::to_string(&f).unwrap() + "\n";
-            Ok::<Bytes, std::convert::Infallible>(Bytes::from(line))
-        });
+/// Frames read from the disk log per refill.
+const REPLAY_BATCH: usize = 256;
 
-    let live = stream::unfold((rx, false), |(mut r, done)| async move {
-        if done { return None; }
-        match r.recv().await {
-            Ok(f) => {
-                let is_exit = f.t == "event" && f.d.starts_with("exit:");
-                let line = serde_json::to_string(&f).unwrap() + "\n";
-                tokio::task::yield_now().await; // nudge hyper to flush
-                Some((Ok::<Bytes, std::convert::Infallible>(Bytes::from(line)), (r, is_exit)))
+/// Yields every frame after `from` exactly once, in seq order. Frames a lagging
+/// receiver missed are refilled from the backlog, or from the disk log when
+/// the backlog has moved on; frames found in neither are reported as one
+/// `gap:<first>-<last>` event (seq 0).
+pub struct FrameCursor {
+    backlog: Backlog,
+    log: Option<Arc<FrameLog>>,
+    rx: broadcast::Receiver<StreamFrame>,
+    last: u64,
+    pending: VecDeque<StreamFrame>,
+    /// still catching up from the backlog or disk; refill again before reading `rx`
+    replaying: bool,
+}
+
+impl FrameCursor {
+    /// Takes no snapshot yet: the first `next` refills from the backlog (and
+    /// disk), and `rx` already holds anything published after that.
+    pub fn new(backlog: Backlog, log: Option<Arc<FrameLog>>, rx: broadcast::Receiver<StreamFrame>, from: u64) -> Self {
+        Self { backlog, log, rx, last: from, pending: VecDeque::new(), replaying: true }
+    }
+
+    pub async fn next(&mut self) -> Option<StreamFrame> {
+        if let Some(f) = self.pending.pop_front() {
+            return Some(f);
+        }
+        while self.replaying {
+            self.backfill().await;
+            if let Some(f) = self.pending.pop_front() {
+                return Some(f);
             }
-            Err(broadcast::error::RecvError::Lagged(_)) => {
-                tokio::task::yield_now().await;
-                Some((Ok(Bytes::new()), (r, false)))
+        }
+        loop {
+            match self.rx.recv().await {
+                Ok(f) if f.seq <= self.last => continue,
+                Ok(f) => {
+                    self.last = f.seq;
+                    return Some(f);
+                }
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    tracing::debug!("stream subscriber lagged by {n} frames, backfilling");
+                    self.backfill().await;
+                    if let Some(f) = self.pending.pop_front() {
+                        return Some(f);
+                    }
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
             }
-            Err(_) => None,
         }
-    });
+    }
+
+    /// Cancel-safe: nothing changes until the disk read has come back.
+    async fn backfill(&mut self) {
+        let first = self.backlog.lock().front().map(|f| f.seq);
+        if let (Some(first), Some(log)) = (first, self.log.clone()) {
+            if first > self.last + 1 {
+                // file reads stay off the async workers
+                let from = self.last + 1;
+                let frames = tokio::task::spawn_blocking(move || log.read(from, first - 1, REPLAY_BATCH))
+                    .await
+                    .unwrap_or_default();
+                if let Some(f) = frames.last() {
+                    self.last = f.seq;
+                    self.pending.extend(frames);
+                    self.replaying = true;
+                    return;
+                }
+            }
+        }
+        self.replaying = false;
+        let b = self.backlog.lock();
+        if let Some(first) = b.front().map(|f| f.seq) {
+            if first > self.last + 1 {
+                let d = format!("gap:{}-{}", self.last + 1, first - 1);
+                self.pending.push_back(StreamFrame::event(0, d));
+            }
+        }
+        for f in b.iter().filter(|f| f.seq > self.last) {
+            self.pending.push_back(f.clone());
+        }
+        if let Some(f) = self.pending.back() {
+            self.last = self.last.max(f.seq);
+        }
+    }
+}
 
-    let all = banner.chain(stream::iter(past)).chain(live);
+/// Event stream for GET /events; `from` (or Last-Event-ID) replays retained
+/// events, otherwise only new ones are sent.
+pub fn stream_events(headers: &HeaderMap, bus: &EventBus, from: Option<u64>) -> Response {
+    let format = Format::accepted(headers);
+    let from = match format {
+        Format::Sse => last_event_id(headers).or(from),
+        _ => from,
+    };
+    format.respond(bus.feed(from))
+}
+
+/// Stream body formats. The binary ones are a sequence of items, each a
+/// big-endian u32 length followed by that many bytes of MessagePack (a map
+/// keyed by field name) or CBOR.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Ndjson,
+    Sse,
+    MsgPack,
+    Cbor,
+}
 
-    axum::response::Response::builder()
+impl Format {
+    /// First match in the Accept header; ndjson when nothing else is asked for.
+    pub fn accepted(headers: &HeaderMap) -> Self {
+        let accept = headers.get(header::ACCEPT).and_then(|v| v.to_str().ok()).unwrap_or("");
+        accept
+            .split(',')
+            .find_map(|t| match t.split(';').next().unwrap_or("").trim() {
+                "text/event-stream" => Some(Format::Sse),
+                "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => Some(Format::MsgPack),
+                "application/cbor" => Some(Format::Cbor),
+                "application/x-ndjson" | "application/json" => Some(Format::Ndjson),
+                _ => None,
+            })
+            .unwrap_or(Format::Ndjson)
+    }
+
+    fn respond<T: Sequenced>(self, items: impl Stream<Item = T> + Send + 'static) -> Response {
+        match self {
+            Format::Ndjson => ndjson(items),
+            Format::Sse => sse(items).into_response(),
+            Format::MsgPack => length_prefixed(items, "application/msgpack", |f| {
+                rmp_serde::to_vec_named(f).map_err(|e| e.to_string())
+            }),
+            Format::Cbor => length_prefixed(items, "application/cbor", |f| {
+                let mut buf = Vec::new();
+                ciborium::into_writer(f, &mut buf).map_err(|e| e.to_string())?;
+                Ok(buf)
+            }),
+        }
+    }
+}
+
+/// Anything served as ndjson or SSE. Seq 0 marks out-of-band items, which
+/// get no SSE id.
+pub trait Sequenced: Serialize + Send + 'static {
+    fn seq(&self) -> u64;
+}
+
+impl Sequenced for StreamFrame {
+    fn seq(&self) -> u64 { self.seq }
+}
+
+impl Sequenced for NodeEvent {
+    fn seq(&self) -> u64 { self.seq }
+}
+
+/// Banner, then the cursor's frames until the exit event.
+fn frames(cursor: FrameCursor) -> impl Stream<Item = StreamFrame> + Send {
+    let banner = stream::once(async {
+        StreamFrame::event(0, "stream-start")
+    });
+    let rest = stream::unfold((cursor, false), |(mut c, done)| async move {
+        if done { return None; }
+        let f = c.next().await?;
+        let is_exit = f.is_exit();
+        tokio::task::yield_now().await; // nudge hyper to flush
+        Some((f, (c, is_exit)))
+    });
+    banner.chain(rest)
+}
+
+fn ndjson<T: Sequenced>(frames: impl Stream<Item = T> + Send + 'static) -> Response {
+    let body = frames.map(|f| {
+        let line = serde_json::to_string(&f).unwrap() + "\n";
+        Ok::<Bytes, std::convert::Infallible>(Bytes::from(line))
+    });
+    Response::builder()
         .status(200)
         .header("content-type", "application/x-ndjson")
         .header("transfer-encoding", "chunked")
-        .body(axum::body::Body::from_stream(all))
+        .body(axum::body::Body::from_stream(body))
+        .unwrap()
+}
+
+fn length_prefixed<T: Sequenced>(
+    items: impl Stream<Item = T> + Send + 'static,
+    content_type: &'static str,
+    encode: fn(&T) -> Result<Vec<u8>, String>,
+) -> Response {
+    let body = items.filter_map(move |f| {
+        let item = encode(&f).map_err(|e| tracing::warn!("stream encode failed: {e}")).ok().map(|payload| {
+            let mut buf = Vec::with_capacity(4 + payload.len());
+            buf.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+            buf.extend_from_slice(&payload);
+            Ok::<Bytes, std::convert::Infallible>(Bytes::from(buf))
+        });
+        std::future::ready(item)
+    });
+    Response::builder()
+        .status(200)
+        .header("content-type", content_type)
+        .body(axum::body::Body::from_stream(body))
         .unwrap()
 }
+
+/// Each event's data is the frame as JSON (PTY output contains bare `\r`,
+/// which SSE data lines can't carry) and its id is the frame's seq.
+fn sse<T: Sequenced>(frames: impl Stream<Item = T> + Send + 'static) -> impl IntoResponse {
+    let events = frames.map(|f| {
+        let ev = Event::default().data(serde_json::to_string(&f).unwrap());
+        // out-of-band frames (seq 0) must not reset the client's Last-Event-ID
+        let ev = if f.seq() > 0 { ev.id(f.seq().to_string()) } else { ev };
+        Ok::<Event, std::convert::Infallible>(ev)
+    });
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+fn last_event_id(headers: &HeaderMap) -> Option<u64> {
+    headers.get("last-event-id")?.to_str().ok()?.trim().parse().ok()
+}
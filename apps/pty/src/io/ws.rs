@@ -0,0 +1,90 @@
+use axum::extract::ws::{Message, WebSocket};
+use futures_util::stream::SplitSink;
+use futures_util::{SinkExt, StreamExt};
+use crate::executor::{self, pty};
+use crate::io::stream::FrameCursor;
+use crate::models::{StreamFrame, WsMessage};
+use crate::state::collab::{self, require, Client, Presence, Role};
+use crate::state::SessionHandle;
+
+type Sink = SplitSink<WebSocket, Message>;
+
+/// Drives one session WebSocket: frames with seq > `from` go out as JSON text
+/// (same shape as the ndjson stream), `WsMessage`s and binary input come in.
+/// The socket is closed after the exit event. The client counts as present
+/// while the socket is open.
+pub async fn session_socket(socket: WebSocket, h: SessionHandle, from: u64, client: Client) {
+    let (mut sink, mut incoming) = socket.split();
+    let _presence = Presence::attach(&h, client.clone());
+    let mut cursor = FrameCursor::new(h.backlog.clone(), h.log.clone(), h.tx.subscribe(), from);
+    if h.finished_at.lock().is_some() && from >= *h.latest_seq.lock() {
+        let _ = sink.close().await;
+        return;
+    }
+
+    loop {
+        tokio::select! {
+            f = cursor.next() => match f {
+                Some(f) => {
+                    if !send(&mut sink, &f).await || f.is_exit() {
+                        break;
+                    }
+                }
+                None => break,
+            },
+            m = incoming.next() => {
+                let res = match m {
+                    Some(Ok(Message::Text(text))) => match serde_json::from_str::<WsMessage>(&text) {
+                        Ok(msg) => handle(&h, &client, msg).await,
+                        Err(e) => Err(format!("bad message: {e}")),
+                    },
+                    Some(Ok(Message::Binary(data))) => {
+                        let data = String::from_utf8_lossy(&data).into_owned();
+                        handle(&h, &client, WsMessage::Input { data }).await
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                    Some(Ok(_)) => Ok(()),
+                };
+                if let Err(e) = res {
+                    let f = StreamFrame::event(0, format!("ws-error:{e}"));
+                    if !send(&mut sink, &f).await {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    let _ = sink.close().await;
+}
+
+async fn handle(h: &SessionHandle, client: &Client, msg: WsMessage) -> Result<(), String> {
+    match msg {
+        WsMessage::Input { data } => {
+            h.collab.lock().may_type(client).map_err(|e| e.to_string())?;
+            pty::write_pty(h, &data).await.map_err(|e| e.to_string())
+        }
+        WsMessage::Resize { cols, rows } => {
+            require(client, Role::Writer, "resize").map_err(|e| e.to_string())?;
+            pty::resize_pty(h, cols, rows).await.map_err(|e| e.to_string())
+        }
+        WsMessage::Signal { signal, group } => {
+            require(client, Role::Writer, "send signals").map_err(|e| e.to_string())?;
+            let sig = executor::parse_signal(&signal).ok_or_else(|| format!("unknown signal {signal:?}"))?;
+            pty::signal_pty(h, sig, group).map_err(|e| e.to_string())
+        }
+        WsMessage::Close => {
+            require(client, Role::Owner, "close the session").map_err(|e| e.to_string())?;
+            pty::close_pty(h).await.map_err(|e| e.to_string())
+        }
+        WsMessage::Lock(req) => {
+            let events = h.collab.lock().lock(client, &req).map_err(|e| e.to_string())?;
+            collab::announce(h, events);
+            Ok(())
+        }
+    }
+}
+
+async fn send(sink: &mut Sink, f: &StreamFrame) -> bool {
+    let text = serde_json::to_string(f).unwrap();
+    sink.send(Message::Text(text)).await.is_ok()
+}
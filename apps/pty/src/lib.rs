@@ -1,6 +1,8 @@
+pub mod auth;
 pub mod models;
 pub mod state;
 pub mod executor;
 pub mod io;
 pub mod config;
+pub mod files;
 pub mod routes;
@@ -1,20 +1,19 @@
-use axum::{routing::get, Router};
+use isolated_exec::{auth, config, routes, state};
 use tracing_subscriber::{fmt, EnvFilter};
 
-mod models;
-mod state;
-mod executor;
-mod io;
-mod config;
-mod routes;
-
 #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
 async fn main() -> anyhow::Result<()> {
     // Initialize logging
     fmt().with_env_filter(EnvFilter::from_default_env()).init();
 
+    // Load sandbox limits before accepting any work
+    let config = config::SandboxConfig::load()?;
+    tracing::info!("sandbox limits: {:?}", config.limits);
+
     // Build state and router
-    let state = state::AppState::new();
+    let state = state::AppState::new(config);
+    state::reaper::spawn(state.clone());
+    auth::spawn_reload(state.clone())?;
     let app = routes::app_router(state);
 
     // Bind listener (use same port as before)
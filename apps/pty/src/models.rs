@@ -1,18 +1,76 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use crate::config::{LimitOverrides, Limits, ProfileConfig};
+use crate::state::collab::Role;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct StreamFrame {
     pub t: String,   // "stdout" | "stderr" | "event"
     pub seq: u64,
     pub d: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enc: Option<String>,         // "base64" for raw output, absent for UTF-8
+    #[serde(default)]
+    pub ts_ms: u64,                  // monotonic, since the job or session started; 0 on seq-0 frames
+}
+
+impl StreamFrame {
+    pub fn event(seq: u64, d: impl Into<String>) -> Self {
+        StreamFrame { t: "event".into(), seq, d: d.into(), enc: None, ts_ms: 0 }
+    }
+
+    pub fn is_exit(&self) -> bool {
+        self.t == "event" && self.d.starts_with("exit:")
+    }
+}
+
+/// One entry of the node-wide GET /events feed.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct NodeEvent {
+    pub seq: u64,
+    pub ts_ms: u64,                  // unix time
+    pub kind: String,                // "job" | "session" | "node"
+    pub id: String,
+    pub event: String,               // "created" | "exited" | "killed" | "resized" | "closed" | "gap"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signal: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,      // why a job was killed: "timeout" | "cancelled" | "closed" | "idle"; "oom" on exit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cols: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rows: Option<u16>,
+}
+
+impl NodeEvent {
+    pub fn new(kind: &str, id: &str, event: &str) -> Self {
+        NodeEvent { kind: kind.into(), id: id.into(), event: event.into(), ..Default::default() }
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct SessionRequest {
     pub mode: String,                // must be "interactive"
-    pub profile: Option<String>,     // e.g., "default" | "posix" | "zsh" | "busybox"
+    pub profile: Option<String>,     // "default" | "bash" | "posix" | "zsh" | "busybox" or one from [profiles]
     pub cols: Option<u16>,
     pub rows: Option<u16>,
+    pub limits: Option<LimitOverrides>,
+    pub isolate: Option<bool>,       // defaults to [namespaces].enable
+    pub seccomp: Option<String>,     // "none" | "default" | "strict" | "no-network"
+    pub encoding: Option<String>,    // "utf8" (default) | "base64"
+    pub env: Option<BTreeMap<String, String>>,  // on top of the profile's
+    pub cwd: Option<String>,         // directory under the sandbox root
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,            // default: the uid's group from passwd, else the uid
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProfileInfo {
+    pub name: String,
+    #[serde(flatten)]
+    pub profile: ProfileConfig,
 }
 
 #[derive(Debug, Serialize)]
@@ -22,6 +80,8 @@ pub struct SessionResponse {
     pub write_url: String,
     pub resize_url: String,
     pub close_url: String,
+    pub ws_url: String,
+    pub owner_token: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -35,10 +95,79 @@ pub struct ResizeRequest {
     pub rows: u16,
 }
 
+/// Client → server messages on GET /sessions/:id/ws.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum WsMessage {
+    Input { data: String },
+    Resize { cols: u16, rows: u16 },
+    Signal {
+        signal: String,
+        #[serde(default)]
+        group: bool,
+    },
+    Close,
+    Lock(LockRequest),
+}
+
+/// POST /sessions/:id/lock, or a `{"type":"lock",...}` WebSocket message
+#[derive(Debug, Deserialize)]
+pub struct LockRequest {
+    pub action: String,              // "acquire" | "request" | "release"
+    pub to: Option<String>,          // release: hand the lock to this client
+    #[serde(default)]
+    pub force: bool,                 // acquire: owner takes it from the holder
+}
+
+#[derive(Debug, Serialize)]
+pub struct LockState {
+    pub holder: Option<String>,
+    pub requests: Vec<String>,       // waiting for a hand-off, oldest first
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClientInfo {
+    pub name: String,
+    pub role: Role,
+    pub connections: usize,          // open streams and sockets
+}
+
+/// GET /sessions/:id/clients
+#[derive(Debug, Serialize)]
+pub struct PresenceResponse {
+    pub clients: Vec<ClientInfo>,
+    pub lock: LockState,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TokenRequest {
+    pub name: String,
+    pub role: Role,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenResponse {
+    pub token: String,
+    pub name: String,
+    pub role: Role,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SignalRequest {
+    pub signal: String,              // "INT" | "TERM" | "HUP" | "TSTP" | "CONT" | "KILL"
+    #[serde(default)]
+    pub group: bool,                 // whole process group instead of the shell alone
+}
+
 #[derive(Debug, Deserialize)]
 pub struct ExecRequest {
     pub cmd: Vec<String>,
     pub cwd: Option<String>,
+    pub limits: Option<LimitOverrides>,
+    pub isolate: Option<bool>,       // defaults to [namespaces].enable
+    pub seccomp: Option<String>,     // "none" | "default" | "strict" | "no-network"
+    pub timeout_ms: Option<u64>,     // may only shorten [timeouts].job_ms
+    pub encoding: Option<String>,    // "utf8" (default) | "base64"
 }
 
 #[derive(Debug, Serialize)]
@@ -53,5 +182,201 @@ pub struct ExecResponse {
 pub struct StatusResponse {
     pub state: String,
     pub exit_code: Option<i32>,
+    pub signal: Option<String>,         // e.g. "SIGKILL" when terminated by a signal
+    pub seq_latest: u64,
+    pub usage: Option<ResourceUsage>,   // None when cgroups are unavailable
+}
+
+/// What a job was started with, after defaults and clamping.
+#[derive(Clone, Debug, Serialize)]
+pub struct JobSpec {
+    #[serde(skip_serializing)]       // GET /jobs/:id has it from the summary
+    pub cmd: Vec<String>,
+    pub cwd: Option<String>,
+    pub limits: Limits,
+    pub isolate: bool,
+    pub seccomp: String,
+    pub encoding: String,
+    pub timeout_ms: Option<u64>,
+}
+
+/// Query of GET /jobs and GET /sessions; every filter given must match.
+#[derive(Debug, Default, Deserialize)]
+pub struct ListFilter {
+    pub state: Option<String>,       // "running" | "exited"
+    pub cmd: Option<String>,         // jobs: substring of the joined command
+    pub profile: Option<String>,     // sessions: profile name
+    pub pid: Option<i32>,
+    pub exit_code: Option<i32>,
+    pub since: Option<u64>,          // created_at >= since (unix ms)
+    pub until: Option<u64>,          // created_at < until (unix ms)
+}
+
+impl ListFilter {
+    pub fn matches_job(&self, j: &JobSummary) -> bool {
+        unset_or(self.cmd.as_deref(), |c| j.cmd.join(" ").contains(c))
+            && self.matches(&j.state, j.pid, j.exit_code, j.created_at)
+    }
+
+    pub fn matches_session(&self, s: &SessionSummary) -> bool {
+        unset_or(self.profile.as_deref(), |p| s.profile.eq_ignore_ascii_case(p))
+            && self.matches(&s.state, s.pid, s.exit_code, s.created_at)
+    }
+
+    fn matches(&self, state: &str, pid: i32, exit_code: Option<i32>, created_at: u64) -> bool {
+        unset_or(self.state.as_deref(), |s| s == state)
+            && unset_or(self.pid, |p| p == pid)
+            && unset_or(self.exit_code, |c| Some(c) == exit_code)
+            && unset_or(self.since, |t| created_at >= t)
+            && unset_or(self.until, |t| created_at < t)
+    }
+}
+
+fn unset_or<T>(filter: Option<T>, pred: impl FnOnce(T) -> bool) -> bool {
+    match filter {
+        Some(v) => pred(v),
+        None => true,
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct JobSummary {
+    pub id: String,
+    pub cmd: Vec<String>,
+    pub created_at: u64,             // unix ms
+    pub state: String,               // "running" | "exited"
+    pub pid: i32,
+    pub exit_code: Option<i32>,
+    pub signal: Option<String>,
+    pub seq_latest: u64,
+    pub bytes: u64,                  // stdout and stderr bytes read so far
+}
+
+/// GET /jobs/:id
+#[derive(Debug, Serialize)]
+pub struct JobDetail {
+    #[serde(flatten)]
+    pub summary: JobSummary,
+    #[serde(flatten)]
+    pub spec: JobSpec,
+    pub finished_at: Option<u64>,    // unix ms
+    pub duration_ms: u64,            // so far, for running jobs
+    pub usage: Option<ResourceUsage>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionSummary {
+    pub id: String,
+    pub profile: String,
+    pub program: String,
+    pub created_at: u64,             // unix ms
+    pub state: String,               // "running" | "exited"
+    pub pid: i32,
+    pub exit_code: Option<i32>,
+    pub signal: Option<String>,
     pub seq_latest: u64,
+    pub bytes: u64,                  // terminal output bytes read so far
+    pub clients: usize,              // attached streams and sockets
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RunRequest {
+    pub cmd: String,                 // one line, typed at the prompt
+    pub timeout_ms: Option<u64>,     // default 30000, may not exceed [timeouts].job_ms
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct RunResponse {
+    pub state: String,               // "completed" | "timeout" | "exited"
+    pub output: String,              // between the command's start and end markers, CRLF as LF
+    pub exit_code: Option<i32>,
+    pub duration_ms: Option<u64>,    // from the start marker to the end marker (or the last output)
+    pub truncated: bool,             // only the last 512 KiB - 1 MiB of output is kept
+    pub at_prompt: Option<bool>,     // on timeout: whether ^C brought the shell back to its prompt
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExpectRequest {
+    pub pattern: String,             // regex, matched against output text (escapes included)
+    pub timeout_ms: Option<u64>,     // default 30000, may not exceed [timeouts].job_ms
+    pub from: Option<u64>,           // search frames after this seq; default 0 (all output)
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ExpectResponse {
+    pub state: String,               // "matched" | "timeout" | "exited"
+    #[serde(rename = "match")]
+    pub matched: Option<String>,
+    pub groups: Vec<Option<String>>, // capture groups 1.., null where a group took no part
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub named: BTreeMap<String, String>,
+    pub seq: Option<u64>,            // frame in which the match ends
+    pub seq_scanned: u64,            // last frame searched
+}
+
+/// GET /sessions/:id/screen
+#[derive(Debug, Serialize)]
+pub struct ScreenResponse {
+    pub cols: u16,
+    pub rows: u16,
+    pub lines: Vec<String>,             // one per row, trailing blanks trimmed
+    pub cursor: CursorPosition,
+    pub cursor_visible: bool,
+    pub alternate_screen: bool,         // vim, less, htop and friends
+    pub seq: u64,                       // last frame reflected on the screen
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attrs: Option<Vec<Vec<ScreenSpan>>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CursorPosition {
+    pub row: u16,
+    pub col: u16,
+}
+
+/// A run of cells in one row that share attributes.
+#[derive(Debug, Serialize)]
+pub struct ScreenSpan {
+    pub col: u16,
+    pub text: String,
+    #[serde(flatten)]
+    pub attrs: CellAttrs,
+}
+
+/// Colors are a palette index ("1") or "#rrggbb"; absent means the terminal default.
+#[derive(Clone, Debug, Default, Serialize, PartialEq, Eq)]
+pub struct CellAttrs {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fg: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bg: Option<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub bold: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub italic: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub underline: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub inverse: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ResourceUsage {
+    pub memory_current: Option<u64>,
+    pub memory_max: Option<u64>,
+    pub cpu_usage_usec: Option<u64>,
+    pub pids_current: Option<u64>,
+    pub pids_max: Option<u64>,
+    pub oom_kills: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReaperResponse {
+    pub sweeps: u64,
+    pub sessions_hung_up: u64,
+    pub sessions_killed: u64,
+    pub sessions_evicted: u64,
+    pub jobs_evicted: u64,
+    pub jobs: usize,
+    pub sessions: usize,
 }
@@ -1,73 +1,172 @@
 use axum::{extract::{Path, State, Query}, routing::{get, post}, Json, Router};
-use axum::http::StatusCode;
-use tower_http::services::ServeDir;
+use axum::extract::ws::WebSocketUpgrade;
+use axum::http::{header, HeaderMap, StatusCode};
+use futures_util::StreamExt;
 use serde::Deserialize;
-use tokio::fs;
+use crate::auth;
+use crate::config::RunAs;
+use crate::files::PathError;
+use crate::io::encoding::Encoding;
 use crate::models::*;
-use crate::state::{AppState, ids};
-use crate::executor::{pty, spawn};
+use crate::io::stream::FrameCursor;
+use crate::state::{events, AppState, JobHandle, SessionHandle, ids};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use crate::state::collab::{self, require, Client, Denied, Presence, Role};
+use crate::executor::{self, pty, seccomp, spawn};
+use nix::sys::signal::Signal;
 
 pub fn app_router(state: AppState) -> Router {
-    // Serve static files from /sandbox/preview on the host
-    let preview_service = ServeDir::new("/sandbox/preview");
-    
     Router::new()
         .route("/health", get(|| async { "ok" }))
         .route("/sandbox/*path", get(get_file))
         .route("/sandbox/*path", post(put_file))
         .route("/exec", post(exec))
-        .route("/sessions", post(start_session))
+        .route("/sessions", post(start_session).get(list_sessions))
         .route("/sessions/:id/stream", get(stream_session))
+        .route("/sessions/:id/ws", get(ws_session))
         .route("/sessions/:id/write", post(write_session))
         .route("/sessions/:id/resize", post(resize_session))
         .route("/sessions/:id/close", post(close_session))
+        .route("/sessions/:id/status", get(status_session))
+        .route("/sessions/:id/signal", post(signal_session))
+        .route("/sessions/:id/recording.cast", get(session_recording))
+        .route("/sessions/:id/screen", get(session_screen))
+        .route("/sessions/:id/run", post(run_session))
+        .route("/sessions/:id/expect", post(expect_session))
+        .route("/sessions/:id/tokens", post(mint_session_token))
+        .route("/sessions/:id/lock", post(lock_session))
+        .route("/sessions/:id/clients", get(session_clients))
+        .route("/jobs", get(list_jobs))
+        .route("/jobs/:id", get(job_detail))
         .route("/stream/:id", get(stream_job))
         .route("/stream/:id/close", post(close_job_stream))
+        .route("/stream/:id/expect", post(expect_job))
         .route("/status/:id", get(status_job))
-        .nest_service("/preview", preview_service) // 👈 serve static files here
+        .route("/status/:id/signal", post(signal_job))
+        .route("/status/:id/cancel", post(cancel_job))
+        .route("/reaper", get(reaper_stats))
+        .route("/events", get(events))
+        .route("/profiles", get(list_profiles))
+        .route("/preview", get(get_preview))
+        .route("/preview/", get(get_preview))
+        .route("/preview/*path", get(get_preview))
+        .layer(axum::middleware::from_fn_with_state(state.clone(), auth::require_signature))
         .with_state(state)
 }
 
 #[derive(Deserialize)]
 struct FromParam { from: Option<u64> }
 
+#[derive(Deserialize)]
+struct TokenParam { token: Option<String> }
+
+/// The client behind a session request, from `?token=` or the token header;
+/// without either, an anonymous viewer.
+fn session_client(h: &SessionHandle, headers: &HeaderMap, q: &TokenParam) -> Result<Client, (StatusCode, String)> {
+    let token = q.token.as_deref().or_else(|| headers.get(collab::SESSION_TOKEN).and_then(|v| v.to_str().ok()));
+    h.collab.lock().client(token).map_err(denied)
+}
+
+fn denied(e: Denied) -> (StatusCode, String) {
+    let status = match e {
+        Denied::Token => StatusCode::UNAUTHORIZED,
+        Denied::Role(..) => StatusCode::FORBIDDEN,
+        Denied::Locked(_) | Denied::Taken(_) => StatusCode::CONFLICT,
+        Denied::Invalid(_) => StatusCode::BAD_REQUEST,
+    };
+    (status, e.to_string())
+}
+
 #[derive(Deserialize)]
 struct FileWriteBody {
     content: String,
 }
 
-// GET /files/:path
+fn path_error(e: PathError) -> (StatusCode, String) {
+    let status = match e {
+        PathError::Invalid(_) => StatusCode::BAD_REQUEST,
+        PathError::Escape => StatusCode::FORBIDDEN,
+        PathError::NotFound => StatusCode::NOT_FOUND,
+        PathError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (status, e.to_string())
+}
+
+// GET /sandbox/*path
 async fn get_file(
+    State(state): State<AppState>,
     Path(path): Path<String>,
 ) -> Result<String, (StatusCode, String)> {
-    let full_path = format!("/sandbox/{}", path); // adjust base dir if needed
-    match fs::read_to_string(&full_path).await {
-        Ok(content) => Ok(content),
-        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-            Err((StatusCode::NOT_FOUND, format!("file not found: {full_path}")))
-        }
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
-    }
+    state.files.read(&path).await.map_err(path_error)
 }
 
-// POST /files/:path
+// POST /sandbox/*path
 async fn put_file(
+    State(state): State<AppState>,
     Path(path): Path<String>,
     Json(body): Json<FileWriteBody>,
 ) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
-    let full_path = format!("/sandbox/{}", path);
-    if let Some(parent) = std::path::Path::new(&full_path).parent() {
-        if let Err(e) = fs::create_dir_all(parent).await {
-            return Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
+    let full_path = state.files.write(&path, body.content).await.map_err(path_error)?;
+    Ok(Json(serde_json::json!({ "ok": true, "path": full_path })))
+}
+
+// GET /preview/*path: static files from <files root>/preview, resolved the
+// same way as /sandbox so symlinks can't reach outside the root
+async fn get_preview(
+    State(state): State<AppState>,
+    path: Option<Path<String>>,
+) -> Result<impl axum::response::IntoResponse, (StatusCode, String)> {
+    let mut rel = format!("preview/{}", path.map(|Path(p)| p).unwrap_or_default());
+    if rel.ends_with('/') {
+        rel.push_str("index.html");
+    }
+    let body = match state.files.read_bytes(&rel).await {
+        Err(PathError::Invalid("is a directory")) => {
+            rel.push_str("/index.html");
+            state.files.read_bytes(&rel).await
         }
+        r => r,
+    }
+    .map_err(path_error)?;
+    let mime = mime_guess::from_path(&rel).first_or_octet_stream();
+    Ok(([(header::CONTENT_TYPE, mime.to_string())], body))
+}
+
+fn seccomp_profile<'a>(state: &'a AppState, requested: Option<&'a str>) -> Result<&'a str, (StatusCode, String)> {
+    let name = requested.unwrap_or(&state.config.seccomp.profile);
+    if !seccomp::PROFILES.contains(&name) {
+        return Err((StatusCode::BAD_REQUEST, format!("unknown seccomp profile {name:?}, expected one of {:?}", seccomp::PROFILES)));
     }
+    Ok(name)
+}
+
+fn output_encoding(requested: Option<&str>) -> Result<Encoding, (StatusCode, String)> {
+    Encoding::named(requested).ok_or_else(|| {
+        (StatusCode::BAD_REQUEST, format!("unknown encoding {:?}, expected one of {:?}", requested.unwrap_or_default(), Encoding::NAMES))
+    })
+}
 
-    match fs::write(&full_path, body.content.as_bytes()).await {
-        Ok(_) => Ok(Json(serde_json::json!({ "ok": true, "path": full_path }))),
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+/// Credentials for a session's `uid`/`gid`. A uid with a passwd entry also
+/// brings its primary group, HOME, USER and LOGNAME.
+fn run_as(uid: Option<u32>, gid: Option<u32>, env: &mut Vec<(String, String)>) -> Option<RunAs> {
+    if uid.is_none() && gid.is_none() {
+        return None;
     }
+    let uid = uid.unwrap_or_else(|| nix::unistd::geteuid().as_raw());
+    let user = nix::unistd::User::from_uid(uid.into()).ok().flatten();
+    if let Some(u) = &user {
+        set_env(env, "HOME", &u.dir.display().to_string());
+        set_env(env, "USER", &u.name);
+        set_env(env, "LOGNAME", &u.name);
+    }
+    let gid = gid.or(user.map(|u| u.gid.as_raw())).unwrap_or(uid);
+    Some(RunAs { uid, gid })
 }
 
+fn set_env(env: &mut Vec<(String, String)>, key: &str, value: &str) {
+    env.retain(|(k, _)| k != key);
+    env.push((key.to_string(), value.to_string()));
+}
 
 async fn start_session(
     State(state): State<AppState>,
@@ -79,8 +178,35 @@ async fn start_session(
     let id = ids::new_id("s");
     let cols = req.cols.unwrap_or(120);
     let rows = req.rows.unwrap_or(32);
-    let h = pty::spawn_pty_shell(req.profile.clone(), cols, rows)
+    let mut shell = state.config.resolve_profile(req.profile.as_deref()).ok_or_else(|| {
+        let names: Vec<_> = state.config.profiles().into_keys().collect();
+        (StatusCode::BAD_REQUEST, format!("unknown profile {:?}, expected one of {names:?}", req.profile.as_deref().unwrap_or_default()))
+    })?;
+    let isolate = req.isolate.unwrap_or(state.config.namespaces.enable);
+    if (req.uid.is_some() || req.gid.is_some()) && isolate && state.namespaces.as_ref().is_some_and(|ns| ns.user()) {
+        return Err((StatusCode::BAD_REQUEST, "uid/gid can't be combined with isolate: the user namespace maps only root".into()));
+    }
+    shell.user = run_as(req.uid, req.gid, &mut shell.env);
+    for (k, v) in req.env.iter().flatten() {
+        if k.is_empty() || k.contains(['=', '\0']) || v.contains('\0') {
+            return Err((StatusCode::BAD_REQUEST, format!("invalid environment variable {k:?}")));
+        }
+        set_env(&mut shell.env, k, v);
+    }
+    if let Some(cwd) = &req.cwd {
+        shell.dir = Some(state.files.open_dir(cwd).await.map_err(path_error)?);
+    }
+    let profile = seccomp_profile(&state, req.seccomp.as_deref())?;
+    let enc = output_encoding(req.encoding.as_deref())?;
+    let iso = state.isolation(&id, req.limits.as_ref(), req.isolate, profile)
         .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let h = pty::spawn_pty_shell(shell, cols, rows, iso, enc, state.frame_log(&id))
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    state.events.publish(NodeEvent { cols: Some(cols), rows: Some(rows), ..NodeEvent::new("session", &id, "created") });
+    let cursor = FrameCursor::new(h.backlog.clone(), None, h.tx.subscribe(), 0);
+    let (code, signal) = (h.exit_code.clone(), h.exit_signal.clone());
+    tokio::spawn(events::watch(state.events.clone(), NodeEvent::new("session", &id, ""), cursor, move || (*code.lock(), *signal.lock())));
+    let owner_token = h.collab.lock().owner_token().to_string();
     state.sessions.write().await.insert(id.clone(), h);
 
     Ok(Json(SessionResponse {
@@ -89,6 +215,8 @@ async fn start_session(
         write_url:  format!("/sessions/{}/write", id),
         resize_url: format!("/sessions/{}/resize", id),
         close_url:  format!("/sessions/{}/close", id),
+        ws_url:     format!("/sessions/{}/ws?from=0", id),
+        owner_token,
     }))
 }
 
@@ -96,24 +224,210 @@ async fn stream_session(
     State(state): State<AppState>,
     Path(id): Path<String>,
     Query(q): Query<FromParam>,
+    Query(t): Query<TokenParam>,
+    headers: HeaderMap,
 ) -> Result<impl axum::response::IntoResponse, (StatusCode, String)> {
-    let guard = state.sessions.read().await;
-    let Some(h) = guard.get(&id) else { return Err((StatusCode::NOT_FOUND, "session not found".into())); };
+    let h = state.sessions.read().await.get(&id).cloned()
+        .ok_or((StatusCode::NOT_FOUND, "session not found".to_string()))?;
+    let client = session_client(&h, &headers, &t)?;
     let rx = h.tx.subscribe();
-    let backlog = {
-        let b = h.backlog.lock();
-        b.iter().cloned().collect::<Vec<_>>()
+    let resp = crate::io::stream::stream_with_backlog(&headers, h.backlog.clone(), h.log.clone(), rx, q.from.unwrap_or(0));
+    // present until the response body is dropped
+    let presence = Presence::attach(&h, client);
+    let (parts, body) = resp.into_parts();
+    let body = body.into_data_stream().map(move |chunk| {
+        let _present = &presence;
+        chunk
+    });
+    Ok(axum::response::Response::from_parts(parts, axum::body::Body::from_stream(body)))
+}
+
+/// GET /sessions/:id/ws — output, input, resize and signals over one socket
+async fn ws_session(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(q): Query<FromParam>,
+    Query(t): Query<TokenParam>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> Result<impl axum::response::IntoResponse, (StatusCode, String)> {
+    let h = state.sessions.read().await.get(&id).cloned()
+        .ok_or((StatusCode::NOT_FOUND, "session not found".to_string()))?;
+    let client = session_client(&h, &headers, &t)?;
+    let from = q.from.unwrap_or(0);
+    Ok(ws.on_upgrade(move |socket| crate::io::ws::session_socket(socket, h, from, client)))
+}
+
+/// POST /sessions/:id/tokens — owner only: an attach token for another client
+async fn mint_session_token(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(t): Query<TokenParam>,
+    headers: HeaderMap,
+    Json(req): Json<TokenRequest>,
+) -> Result<Json<TokenResponse>, (StatusCode, String)> {
+    let sessions = state.sessions.read().await;
+    let Some(h) = sessions.get(&id) else { return Err((StatusCode::NOT_FOUND, "session not found".into())); };
+    let client = session_client(h, &headers, &t)?;
+    let token = h.collab.lock().mint(&client, &req.name, req.role).map_err(denied)?;
+    Ok(Json(TokenResponse { token, name: req.name, role: req.role }))
+}
+
+/// POST /sessions/:id/lock — acquire, request or release (hand off) the input lock
+async fn lock_session(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(t): Query<TokenParam>,
+    headers: HeaderMap,
+    Json(req): Json<LockRequest>,
+) -> Result<Json<LockState>, (StatusCode, String)> {
+    let sessions = state.sessions.read().await;
+    let Some(h) = sessions.get(&id) else { return Err((StatusCode::NOT_FOUND, "session not found".into())); };
+    let client = session_client(h, &headers, &t)?;
+    let (events, lock) = {
+        let mut collab = h.collab.lock();
+        (collab.lock(&client, &req).map_err(denied)?, collab.state())
+    };
+    collab::announce(h, events);
+    Ok(Json(lock))
+}
+
+/// GET /sessions/:id/clients — who is attached, and the input lock
+async fn session_clients(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<PresenceResponse>, (StatusCode, String)> {
+    let sessions = state.sessions.read().await;
+    let Some(h) = sessions.get(&id) else { return Err((StatusCode::NOT_FOUND, "session not found".into())); };
+    let collab = h.collab.lock();
+    Ok(Json(PresenceResponse { clients: collab.clients(), lock: collab.state() }))
+}
+
+/// GET /profiles — session profiles available on this node
+async fn list_profiles(State(state): State<AppState>) -> Json<Vec<ProfileInfo>> {
+    let profiles = state.config.profiles().into_iter().map(|(name, profile)| ProfileInfo { name, profile });
+    Json(profiles.collect())
+}
+
+/// POST /sessions/:id/run — one command at the prompt, its output and exit status
+async fn run_session(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(t): Query<TokenParam>,
+    headers: HeaderMap,
+    Json(req): Json<RunRequest>,
+) -> Result<Json<RunResponse>, (StatusCode, String)> {
+    let h = state.sessions.read().await.get(&id).cloned()
+        .ok_or((StatusCode::NOT_FOUND, "session not found".to_string()))?;
+    let client = session_client(&h, &headers, &t)?;
+    h.collab.lock().may_type(&client).map_err(denied)?;
+    if req.cmd.trim().is_empty() || req.cmd.contains(['\r', '\n']) {
+        return Err((StatusCode::BAD_REQUEST, "cmd must be a single non-empty line".into()));
+    }
+    if h.finished_at.lock().is_some() {
+        return Err((StatusCode::CONFLICT, "session has exited".into()));
+    }
+    if !h.markers {
+        return Err((StatusCode::CONFLICT, "session shell has no command markers (bash or zsh profiles with integration)".into()));
+    }
+    let Ok(_running) = h.run_lock.try_lock() else {
+        return Err((StatusCode::CONFLICT, "another command is running in this session".into()));
     };
-    Ok(crate::io::stream::ndjson_stream_with_backlog(backlog, rx, q.from.unwrap_or(0)))
+    let resp = crate::executor::integration::run(&h, &req.cmd, wait_timeout(&state, req.timeout_ms)).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(resp))
+}
+
+/// How long /run and /expect block: `timeout_ms`, 30 s by default, at most `job_ms`.
+fn wait_timeout(state: &AppState, timeout_ms: Option<u64>) -> std::time::Duration {
+    let limit = state.config.timeouts.job_ms;
+    let ms = timeout_ms.filter(|&ms| ms > 0).unwrap_or(30_000);
+    std::time::Duration::from_millis(if limit > 0 { ms.min(limit) } else { ms })
+}
+
+fn expect_pattern(req: &ExpectRequest) -> Result<regex::Regex, (StatusCode, String)> {
+    regex::RegexBuilder::new(&req.pattern)
+        .size_limit(1 << 20)
+        .build()
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("bad pattern: {e}")))
+}
+
+/// POST /sessions/:id/expect — wait for a regex to appear in the session's output
+async fn expect_session(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(req): Json<ExpectRequest>,
+) -> Result<Json<ExpectResponse>, (StatusCode, String)> {
+    let re = expect_pattern(&req)?;
+    let from = req.from.unwrap_or(0);
+    let h = state.sessions.read().await.get(&id).cloned()
+        .ok_or((StatusCode::NOT_FOUND, "session not found".to_string()))?;
+    let cursor = FrameCursor::new(h.backlog.clone(), h.log.clone(), h.tx.subscribe(), from);
+    let done = h.finished_at.lock().is_some() && from >= *h.latest_seq.lock();
+    if done {
+        return Ok(Json(ExpectResponse { state: "exited".into(), seq_scanned: from, ..Default::default() }));
+    }
+    Ok(Json(crate::io::expect::expect(cursor, &re, from, wait_timeout(&state, req.timeout_ms)).await))
+}
+
+/// POST /stream/:id/expect — same for a job's stdout and stderr
+async fn expect_job(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(req): Json<ExpectRequest>,
+) -> Result<Json<ExpectResponse>, (StatusCode, String)> {
+    let re = expect_pattern(&req)?;
+    let from = req.from.unwrap_or(0);
+    let (backlog, log, rx, done) = {
+        let jobs = state.jobs.read().await;
+        let h = jobs.get(&id).ok_or((StatusCode::NOT_FOUND, "job not found".to_string()))?;
+        let done = h.finished_at.lock().is_some() && from >= h.latest_seq.load(std::sync::atomic::Ordering::Relaxed);
+        (h.backlog.clone(), h.log.clone(), h.tx.subscribe(), done)
+    };
+    let cursor = FrameCursor::new(backlog, log, rx, from);
+    if done {
+        return Ok(Json(ExpectResponse { state: "exited".into(), seq_scanned: from, ..Default::default() }));
+    }
+    Ok(Json(crate::io::expect::expect(cursor, &re, from, wait_timeout(&state, req.timeout_ms)).await))
+}
+
+#[derive(Deserialize)]
+struct ScreenParams { #[serde(default)] attrs: bool }
+
+/// GET /sessions/:id/screen — what the terminal shows right now
+async fn session_screen(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(q): Query<ScreenParams>,
+) -> Result<Json<ScreenResponse>, (StatusCode, String)> {
+    let guard = state.sessions.read().await;
+    let Some(h) = guard.get(&id) else { return Err((StatusCode::NOT_FOUND, "session not found".into())); };
+    let screen = h.screen.lock();
+    let resp = crate::io::screen::snapshot(&screen, *h.latest_seq.lock(), q.attrs);
+    Ok(Json(resp))
+}
+
+/// GET /sessions/:id/recording.cast — asciicast v2
+async fn session_recording(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<axum::response::Response, (StatusCode, String)> {
+    let guard = state.sessions.read().await;
+    let Some(h) = guard.get(&id) else { return Err((StatusCode::NOT_FOUND, "session not found".into())); };
+    Ok(crate::io::cast::recording(h))
 }
 
 async fn write_session(
     State(state): State<AppState>,
     Path(id): Path<String>,
+    Query(t): Query<TokenParam>,
+    headers: HeaderMap,
     Json(body): Json<WriteRequest>
 ) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
     let guard = state.sessions.read().await;
     let Some(h) = guard.get(&id) else { return Err((StatusCode::NOT_FOUND, "session not found".into())); };
+    let client = session_client(h, &headers, &t)?;
+    h.collab.lock().may_type(&client).map_err(denied)?;
     tracing::debug!("write_session: {} bytes to {}", body.data.len(), id);
     pty::write_pty(h, &body.data).await
         .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
@@ -123,10 +437,13 @@ async fn write_session(
 async fn resize_session(
     State(state): State<AppState>,
     Path(id): Path<String>,
+    Query(t): Query<TokenParam>,
+    headers: HeaderMap,
     Json(body): Json<ResizeRequest>
 ) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
     let guard = state.sessions.read().await;
     let Some(h) = guard.get(&id) else { return Err((StatusCode::NOT_FOUND, "session not found".into())); };
+    require(&session_client(h, &headers, &t)?, Role::Writer, "resize").map_err(denied)?;
     pty::resize_pty(h, body.cols, body.rows).await
         .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
     Ok(Json(serde_json::json!({ "ok": true })))
@@ -134,10 +451,13 @@ async fn resize_session(
 
 async fn close_session(
     State(state): State<AppState>,
-    Path(id): Path<String>
+    Path(id): Path<String>,
+    Query(t): Query<TokenParam>,
+    headers: HeaderMap,
 ) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
     let guard = state.sessions.read().await;
     let Some(h) = guard.get(&id) else { return Err((StatusCode::NOT_FOUND, "session not found".into())); };
+    require(&session_client(h, &headers, &t)?, Role::Owner, "close the session").map_err(denied)?;
     pty::close_pty(h).await
         .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
     Ok(Json(serde_json::json!({ "ok": true })))
@@ -151,74 +471,277 @@ async fn exec(
         return Err((StatusCode::BAD_REQUEST, "cmd required".into()));
     }
     let id = ids::new_id("j");
-    let handle = spawn::spawn_noninteractive(req.cmd.clone(), req.cwd.clone()).await;
+    let profile = seccomp_profile(&state, req.seccomp.as_deref())?;
+    let enc = output_encoding(req.encoding.as_deref())?;
+    let iso = state.isolation(&id, req.limits.as_ref(), req.isolate, profile)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let timeout = job_timeout(&state, req.timeout_ms);
+    let spec = JobSpec {
+        cmd: req.cmd.clone(),
+        cwd: req.cwd.clone(),
+        limits: iso.limits,
+        isolate: iso.namespaces.is_some(),
+        seccomp: profile.to_string(),
+        encoding: enc.name().to_string(),
+        timeout_ms: timeout.after.map(|d| d.as_millis() as u64),
+    };
+    let handle = spawn::spawn_noninteractive(spec, iso, timeout, enc, state.frame_log(&id)).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    state.events.publish(NodeEvent::new("job", &id, "created"));
+    let cursor = FrameCursor::new(handle.backlog.clone(), None, handle.tx.subscribe(), 0);
+    let (code, signal) = (handle.exit_code.clone(), handle.exit_signal.clone());
+    tokio::spawn(events::watch(state.events.clone(), NodeEvent::new("job", &id, ""), cursor, move || (*code.lock(), *signal.lock())));
     state.jobs.write().await.insert(id.clone(), handle);
     Ok(Json(ExecResponse {
         job_id: id.clone(),
         stream_url: format!("/stream/{id}?from=0"),
         status_url: format!("/status/{id}"),
-        cancel_url: String::new(),
+        cancel_url: format!("/status/{id}/cancel"),
     }))
 }
 
+fn job_timeout(state: &AppState, requested: Option<u64>) -> spawn::Timeout {
+    let t = &state.config.timeouts;
+    let ms = match (t.job_ms, requested) {
+        (0, r) => r.filter(|&ms| ms > 0),
+        (c, Some(r)) if r > 0 => Some(r.min(c)),
+        (c, _) => Some(c),
+    };
+    spawn::Timeout {
+        after: ms.map(std::time::Duration::from_millis),
+        grace: std::time::Duration::from_millis(t.grace_ms),
+    }
+}
 
 async fn stream_job(
     State(state): State<AppState>,
     Path(id): Path<String>,
     Query(q): Query<FromParam>,
+    headers: HeaderMap,
 ) -> Result<impl axum::response::IntoResponse, (StatusCode, String)> {
-    let jobs = state.jobs.read().await;
-    let Some(h) = jobs.get(&id) else { return Err((StatusCode::NOT_FOUND, "job not found".into())); };
-    let rx = h.tx.subscribe();
-    let backlog = {
-        let b = h.backlog.lock();
-        b.iter().cloned().collect::<Vec<_>>()
+    let (backlog, log, rx) = {
+        let jobs = state.jobs.read().await;
+        let Some(h) = jobs.get(&id) else { return Err((StatusCode::NOT_FOUND, "job not found".into())); };
+        (h.backlog.clone(), h.log.clone(), h.tx.subscribe())
     };
-    Ok(crate::io::stream::ndjson_stream_with_backlog(backlog, rx, q.from.unwrap_or(0)))
+    Ok(crate::io::stream::stream_with_backlog(&headers, backlog, log, rx, q.from.unwrap_or(0)))
 }
 
-/// POST /stream/:id/close — gracefully close a running job stream
+/// POST /stream/:id/close — stop streaming a job and kill its process group
 async fn close_job_stream(
     State(state): State<AppState>,
     Path(id): Path<String>,
 ) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
-    use tracing::info;
-
-    let mut jobs = state.jobs.write().await;
-    if let Some(mut handle) = jobs.remove(&id) {
-        info!("Closing job {}", id);
-
-        // Try to kill the process
-        if let Ok(mut child) = handle.child.lock().await.try_wait() {
-            if child.is_none() {
-                info!("Job {} already exited", id);
-            } else {
-                if let Err(e) = handle.child.lock().await.kill().await {
-                    info!("Job {} kill failed: {}", id, e);
-                } else {
-                    info!("Job {} terminated successfully", id);
-                }
-            }
+    let Some(handle) = state.jobs.write().await.remove(&id) else {
+        return Err((StatusCode::NOT_FOUND, format!("job {id} not found")));
+    };
+    tracing::info!("Closing job {}", id);
+    if handle.finished_at.lock().is_none() {
+        if let Err(e) = spawn::signal_job(&handle, Signal::SIGKILL, true) {
+            tracing::info!("Job {} kill failed: {}", id, e);
+        } else {
+            state.events.publish(NodeEvent { signal: Some("SIGKILL".into()), reason: Some("closed".into()), ..NodeEvent::new("job", &id, "killed") });
         }
+    }
+    state.events.publish(NodeEvent::new("job", &id, "closed"));
+    Ok(Json(serde_json::json!({ "ok": true, "closed": id })))
+}
 
-        Ok(Json(serde_json::json!({ "ok": true, "closed": id })))
-    } else {
-        Err((StatusCode::NOT_FOUND, format!("job {id} not found")))
+fn parse_signal(name: &str) -> Result<Signal, (StatusCode, String)> {
+    executor::parse_signal(name).ok_or_else(|| {
+        (StatusCode::BAD_REQUEST, format!("unknown signal {name:?}, expected one of {:?}", executor::SIGNALS))
+    })
+}
+
+fn signal_error(e: nix::Error) -> (StatusCode, String) {
+    match e {
+        nix::Error::ESRCH => (StatusCode::CONFLICT, "process has exited".into()),
+        e => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
     }
 }
 
+/// POST /status/:id/signal
+async fn signal_job(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(req): Json<SignalRequest>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let sig = parse_signal(&req.signal)?;
+    let jobs = state.jobs.read().await;
+    let Some(h) = jobs.get(&id) else { return Err((StatusCode::NOT_FOUND, "job not found".into())); };
+    if h.finished_at.lock().is_some() {
+        return Err((StatusCode::CONFLICT, "job has exited".into()));
+    }
+    spawn::signal_job(h, sig, req.group).map_err(signal_error)?;
+    Ok(Json(serde_json::json!({ "ok": true, "signal": sig.as_str(), "group": req.group })))
+}
+
+/// POST /status/:id/cancel — SIGTERM to the job's process group, SIGKILL after `[timeouts].grace_ms`
+async fn cancel_job(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let jobs = state.jobs.read().await;
+    let Some(h) = jobs.get(&id) else { return Err((StatusCode::NOT_FOUND, "job not found".into())); };
+    if h.finished_at.lock().is_some() {
+        return Err((StatusCode::CONFLICT, "job has exited".into()));
+    }
+    spawn::cancel_job(h);
+    Ok(Json(serde_json::json!({ "ok": true })))
+}
+
+/// POST /sessions/:id/signal — `group` targets the terminal's foreground process group
+async fn signal_session(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(t): Query<TokenParam>,
+    headers: HeaderMap,
+    Json(req): Json<SignalRequest>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let sig = parse_signal(&req.signal)?;
+    let sessions = state.sessions.read().await;
+    let Some(h) = sessions.get(&id) else { return Err((StatusCode::NOT_FOUND, "session not found".into())); };
+    require(&session_client(h, &headers, &t)?, Role::Writer, "send signals").map_err(denied)?;
+    if h.finished_at.lock().is_some() {
+        return Err((StatusCode::CONFLICT, "session has exited".into()));
+    }
+    pty::signal_pty(h, sig, req.group).map_err(signal_error)?;
+    Ok(Json(serde_json::json!({ "ok": true, "signal": sig.as_str(), "group": req.group })))
+}
 
 async fn status_job(
     State(state): State<AppState>,
     Path(id): Path<String>
 ) -> Result<Json<StatusResponse>, (StatusCode, String)> {
-    let (state_str, exit_code, seq_latest) = {
-        let jobs = state.jobs.read().await;
-        let Some(h) = jobs.get(&id) else { return Err((StatusCode::NOT_FOUND, "job not found".into())); };
-        let exit_code = *h.exit_code.lock();
-        let seq_latest = h.latest_seq.load(std::sync::atomic::Ordering::Relaxed);
-        let state_str = if exit_code.is_some() { "exited".into() } else { "running".into() };
-        (state_str, exit_code, seq_latest)
-    };
-    Ok(Json(StatusResponse { state: state_str, exit_code, seq_latest }))
+    let jobs = state.jobs.read().await;
+    let Some(h) = jobs.get(&id) else { return Err((StatusCode::NOT_FOUND, "job not found".into())); };
+    let seq_latest = h.latest_seq.load(std::sync::atomic::Ordering::Relaxed);
+    let resp = status_response(h.finished_at.lock().is_some(), *h.exit_code.lock(), *h.exit_signal.lock(), seq_latest, h.cgroup.as_deref());
+    Ok(Json(resp))
+}
+
+/// GET /sessions/:id/status — same shape as /status/:id
+async fn status_session(
+    State(state): State<AppState>,
+    Path(id): Path<String>
+) -> Result<Json<StatusResponse>, (StatusCode, String)> {
+    let sessions = state.sessions.read().await;
+    let Some(h) = sessions.get(&id) else { return Err((StatusCode::NOT_FOUND, "session not found".into())); };
+    let seq_latest = *h.latest_seq.lock();
+    let resp = status_response(h.finished_at.lock().is_some(), *h.exit_code.lock(), *h.exit_signal.lock(), seq_latest, h.cgroup.as_deref());
+    Ok(Json(resp))
+}
+
+fn unix_ms(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+fn check_filter(f: &ListFilter) -> Result<(), (StatusCode, String)> {
+    match f.state.as_deref() {
+        None | Some("running" | "exited") => Ok(()),
+        Some(s) => Err((StatusCode::BAD_REQUEST, format!("unknown state {s:?}, expected \"running\" or \"exited\""))),
+    }
+}
+
+fn job_summary(id: &str, h: &JobHandle) -> JobSummary {
+    JobSummary {
+        id: id.to_string(),
+        cmd: h.spec.cmd.clone(),
+        created_at: unix_ms(h.created_at),
+        state: if h.finished_at.lock().is_some() { "exited".into() } else { "running".into() },
+        pid: h.pid,
+        exit_code: *h.exit_code.lock(),
+        signal: h.exit_signal.lock().map(crate::executor::signal_name),
+        seq_latest: h.latest_seq.load(std::sync::atomic::Ordering::Relaxed),
+        bytes: h.bytes.load(std::sync::atomic::Ordering::Relaxed),
+    }
+}
+
+/// GET /jobs — jobs still held by this node, oldest first
+async fn list_jobs(
+    State(state): State<AppState>,
+    Query(f): Query<ListFilter>,
+) -> Result<Json<Vec<JobSummary>>, (StatusCode, String)> {
+    check_filter(&f)?;
+    let jobs = state.jobs.read().await;
+    let mut out: Vec<_> = jobs.iter().map(|(id, h)| job_summary(id, h)).filter(|j| f.matches_job(j)).collect();
+    out.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+    Ok(Json(out))
+}
+
+/// GET /jobs/:id — summary plus what the job was started with
+async fn job_detail(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<JobDetail>, (StatusCode, String)> {
+    let jobs = state.jobs.read().await;
+    let Some(h) = jobs.get(&id) else { return Err((StatusCode::NOT_FOUND, "job not found".into())); };
+    let finished = *h.finished_at.lock();
+    let ran = finished.unwrap_or_else(Instant::now).duration_since(h.started);
+    Ok(Json(JobDetail {
+        summary: job_summary(&id, h),
+        spec: (*h.spec).clone(),
+        finished_at: finished.map(|_| unix_ms(h.created_at + ran)),
+        duration_ms: ran.as_millis() as u64,
+        usage: h.cgroup.as_deref().map(|cg| cg.usage()),
+    }))
+}
+
+/// GET /sessions — sessions still held by this node, oldest first
+async fn list_sessions(
+    State(state): State<AppState>,
+    Query(f): Query<ListFilter>,
+) -> Result<Json<Vec<SessionSummary>>, (StatusCode, String)> {
+    check_filter(&f)?;
+    let sessions = state.sessions.read().await;
+    let mut out: Vec<_> = sessions
+        .iter()
+        .map(|(id, h)| SessionSummary {
+            id: id.clone(),
+            profile: h.profile.clone(),
+            program: h.shell.clone(),
+            created_at: unix_ms(h.created_at),
+            state: if h.finished_at.lock().is_some() { "exited".into() } else { "running".into() },
+            pid: h.pid,
+            exit_code: *h.exit_code.lock(),
+            signal: h.exit_signal.lock().map(crate::executor::signal_name),
+            seq_latest: *h.latest_seq.lock(),
+            bytes: h.bytes.load(std::sync::atomic::Ordering::Relaxed),
+            clients: h.collab.lock().clients().iter().map(|c| c.connections).sum(),
+        })
+        .filter(|s| f.matches_session(s))
+        .collect();
+    out.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+    Ok(Json(out))
+}
+
+fn status_response(
+    finished: bool,
+    exit_code: Option<i32>,
+    signal: Option<i32>,
+    seq_latest: u64,
+    cgroup: Option<&crate::executor::cgroup::Cgroup>,
+) -> StatusResponse {
+    StatusResponse {
+        state: if finished { "exited".into() } else { "running".into() },
+        exit_code,
+        signal: signal.map(crate::executor::signal_name),
+        seq_latest,
+        usage: cgroup.map(|cg| cg.usage()),
+    }
+}
+
+/// GET /events — lifecycle of every job and session on this node
+async fn events(
+    State(state): State<AppState>,
+    Query(q): Query<FromParam>,
+    headers: HeaderMap,
+) -> axum::response::Response {
+    crate::io::stream::stream_events(&headers, &state.events, q.from)
+}
+
+async fn reaper_stats(State(state): State<AppState>) -> Json<ReaperResponse> {
+    let jobs = state.jobs.read().await.len();
+    let sessions = state.sessions.read().await.len();
+    Json(state.reaper.snapshot(jobs, sessions))
 }
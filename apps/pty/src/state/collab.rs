@@ -0,0 +1,320 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use super::SessionHandle;
+use crate::models::{ClientInfo, LockRequest, LockState};
+
+/// Name of the session's creator.
+pub const OWNER: &str = "owner";
+
+/// Name of requests without a token; they may only read.
+pub const ANONYMOUS: &str = "anonymous";
+
+/// Header carrying an attach token, for clients that don't use `?token=`.
+pub const SESSION_TOKEN: &str = "x-session-token";
+
+/// What a session client may do, most to least. Viewers only read, writers
+/// also type, resize and signal, the owner also closes the session and
+/// hands out tokens.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Owner,
+    Writer,
+    Viewer,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Owner => "owner",
+            Role::Writer => "writer",
+            Role::Viewer => "viewer",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Client {
+    pub name: String,
+    pub role: Role,
+}
+
+impl Client {
+    pub fn owner() -> Self {
+        Client { name: OWNER.into(), role: Role::Owner }
+    }
+
+    pub fn anonymous() -> Self {
+        Client { name: ANONYMOUS.into(), role: Role::Viewer }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Denied {
+    #[error("invalid session token")]
+    Token,
+    #[error("{} clients cannot {1}", .0.as_str())]
+    Role(Role, &'static str),
+    #[error("input is locked by {0}")]
+    Locked(String),
+    #[error("a client named {0:?} already exists")]
+    Taken(String),
+    #[error("{0}")]
+    Invalid(&'static str),
+}
+
+/// Attach tokens, connected clients and the input lock of one session.
+/// While nobody holds the lock every writer may type.
+#[derive(Debug)]
+pub struct Collab {
+    owner_token: String,
+    tokens: HashMap<String, Client>,
+    /// name → (role, open streams and sockets)
+    attached: HashMap<String, (Role, usize)>,
+    holder: Option<String>,
+    /// names waiting for the lock, oldest first
+    requests: Vec<String>,
+}
+
+impl Default for Collab {
+    fn default() -> Self {
+        let owner_token = crate::state::ids::new_id("t");
+        Collab {
+            tokens: HashMap::from([(owner_token.clone(), Client::owner())]),
+            owner_token,
+            attached: HashMap::new(),
+            holder: None,
+            requests: Vec::new(),
+        }
+    }
+}
+
+impl Collab {
+    pub fn owner_token(&self) -> &str {
+        &self.owner_token
+    }
+
+    pub fn client(&self, token: Option<&str>) -> Result<Client, Denied> {
+        match token {
+            None => Ok(Client::anonymous()),
+            Some(t) => self.tokens.get(t).cloned().ok_or(Denied::Token),
+        }
+    }
+
+    pub fn mint(&mut self, by: &Client, name: &str, role: Role) -> Result<String, Denied> {
+        require(by, Role::Owner, "hand out tokens")?;
+        let ok = |c: char| c.is_ascii_alphanumeric() || "._-@".contains(c);
+        if name.is_empty() || name.len() > 64 || !name.chars().all(ok) {
+            return Err(Denied::Invalid("name must be 1-64 of A-Z a-z 0-9 . _ - @"));
+        }
+        if name == ANONYMOUS || self.known(name) {
+            return Err(Denied::Taken(name.into()));
+        }
+        let token = crate::state::ids::new_id("t");
+        self.tokens.insert(token.clone(), Client { name: name.into(), role });
+        Ok(token)
+    }
+
+    fn known(&self, name: &str) -> bool {
+        self.tokens.values().any(|c| c.name == name)
+    }
+
+    /// Checks that `c` may send input right now.
+    pub fn may_type(&self, c: &Client) -> Result<(), Denied> {
+        require(c, Role::Writer, "type")?;
+        match &self.holder {
+            Some(h) if *h != c.name => Err(Denied::Locked(h.clone())),
+            _ => Ok(()),
+        }
+    }
+
+    /// Applies a lock request; returns the event frames it caused.
+    pub fn lock(&mut self, c: &Client, req: &LockRequest) -> Result<Vec<String>, Denied> {
+        require(c, Role::Writer, "take the input lock")?;
+        let mine = self.holder.as_deref() == Some(c.name.as_str());
+        match req.action.as_str() {
+            "acquire" | "request" if self.holder.is_none() || mine || (req.force && c.role == Role::Owner) => {
+                Ok(self.give(Some(c.name.clone())))
+            }
+            "acquire" => Err(Denied::Locked(self.holder.clone().unwrap_or_default())),
+            "request" => {
+                if !self.requests.contains(&c.name) {
+                    self.requests.push(c.name.clone());
+                }
+                Ok(vec![format!("lock-request:{}", c.name)])
+            }
+            "release" if mine || (self.holder.is_some() && c.role == Role::Owner) => match &req.to {
+                Some(to) => {
+                    self.may_hold(to)?;
+                    Ok(self.give(Some(to.clone())))
+                }
+                None => Ok(self.give(None)),
+            },
+            "release" => Err(Denied::Invalid("input lock is not held by this client")),
+            _ => Err(Denied::Invalid("action must be acquire, request or release")),
+        }
+    }
+
+    /// Checks that the lock can be handed to `name`: an attached writer or the owner.
+    fn may_hold(&self, name: &str) -> Result<(), Denied> {
+        let Some(target) = self.tokens.values().find(|c| c.name == name) else {
+            return Err(Denied::Invalid("no client by that name"));
+        };
+        require(target, Role::Writer, "hold the input lock")?;
+        if !self.attached.contains_key(name) {
+            return Err(Denied::Invalid("that client is not attached"));
+        }
+        Ok(())
+    }
+
+    fn give(&mut self, to: Option<String>) -> Vec<String> {
+        if self.holder == to {
+            return Vec::new();
+        }
+        self.requests.retain(|n| Some(n) != to.as_ref());
+        let prev = std::mem::replace(&mut self.holder, to);
+        match (&self.holder, prev) {
+            (Some(to), _) => vec![format!("lock:{to}")],
+            (None, Some(prev)) => vec![format!("unlock:{prev}")],
+            (None, None) => Vec::new(),
+        }
+    }
+
+    pub fn state(&self) -> LockState {
+        LockState { holder: self.holder.clone(), requests: self.requests.clone() }
+    }
+
+    pub fn clients(&self) -> Vec<ClientInfo> {
+        let mut out: Vec<_> = self.attached.iter()
+            .map(|(name, &(role, connections))| ClientInfo { name: name.clone(), role, connections })
+            .collect();
+        out.sort_by(|a, b| a.name.cmp(&b.name));
+        out
+    }
+}
+
+pub fn require(c: &Client, role: Role, what: &'static str) -> Result<(), Denied> {
+    if c.role <= role { Ok(()) } else { Err(Denied::Role(c.role, what)) }
+}
+
+/// One attached stream or socket; announces the client with `join:<role>:<name>`
+/// and, on drop, `leave:<role>:<name>`. A lock holder's last leave releases the lock.
+pub struct Presence {
+    h: SessionHandle,
+    client: Client,
+}
+
+impl Presence {
+    pub fn attach(h: &SessionHandle, client: Client) -> Self {
+        h.collab.lock().attached.entry(client.name.clone()).or_insert((client.role, 0)).1 += 1;
+        announce(h, vec![format!("join:{}:{}", client.role.as_str(), client.name)]);
+        Presence { h: h.clone(), client }
+    }
+}
+
+impl Drop for Presence {
+    fn drop(&mut self) {
+        let mut events = vec![format!("leave:{}:{}", self.client.role.as_str(), self.client.name)];
+        {
+            let mut collab = self.h.collab.lock();
+            let gone = match collab.attached.get_mut(&self.client.name) {
+                Some((_, n)) if *n > 1 => { *n -= 1; false }
+                _ => true,
+            };
+            if gone {
+                collab.attached.remove(&self.client.name);
+                collab.requests.retain(|n| *n != self.client.name);
+                if collab.holder.as_ref() == Some(&self.client.name) {
+                    events.extend(collab.give(None));
+                }
+            }
+        }
+        announce(&self.h, events);
+    }
+}
+
+/// Pushes collaboration events, unless the session has already exited.
+pub fn announce(h: &SessionHandle, events: Vec<String>) {
+    if h.finished_at.lock().is_none() {
+        for e in events {
+            h.push_event(e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn req(action: &str, to: Option<&str>, force: bool) -> LockRequest {
+        LockRequest { action: action.into(), to: to.map(Into::into), force }
+    }
+
+    fn session() -> (Collab, Client, Client, Client) {
+        let mut c = Collab::default();
+        let owner = c.client(Some(&c.owner_token.clone())).unwrap();
+        let alice = c.mint(&owner, "alice", Role::Writer).unwrap();
+        let bob = c.mint(&owner, "bob", Role::Viewer).unwrap();
+        let (alice, bob) = (c.client(Some(&alice)).unwrap(), c.client(Some(&bob)).unwrap());
+        (c, owner, alice, bob)
+    }
+
+    #[test]
+    fn tokens_decide_the_role() {
+        let (mut c, owner, alice, bob) = session();
+        assert_eq!(owner.role, Role::Owner);
+        assert_eq!((alice.name.as_str(), alice.role), ("alice", Role::Writer));
+        assert_eq!(bob.role, Role::Viewer);
+        assert!(matches!(c.client(Some("t_nope")), Err(Denied::Token)));
+        assert!(matches!(c.mint(&alice, "carol", Role::Viewer), Err(Denied::Role(Role::Writer, _))));
+        assert!(matches!(c.mint(&owner, "alice", Role::Viewer), Err(Denied::Taken(_))));
+        assert!(matches!(c.mint(&owner, "a b", Role::Viewer), Err(Denied::Invalid(_))));
+    }
+
+    #[test]
+    fn requests_without_a_token_only_read() {
+        let (mut c, owner, ..) = session();
+        let anon = c.client(None).unwrap();
+        assert_eq!((anon.name.as_str(), anon.role), (ANONYMOUS, Role::Viewer));
+        assert!(matches!(c.may_type(&anon), Err(Denied::Role(Role::Viewer, _))));
+        assert!(matches!(c.lock(&anon, &req("acquire", None, false)), Err(Denied::Role(..))));
+        assert!(matches!(c.mint(&owner, ANONYMOUS, Role::Writer), Err(Denied::Taken(_))));
+    }
+
+    #[test]
+    fn the_lock_admits_only_its_holder() {
+        let (mut c, owner, alice, bob) = session();
+        assert!(c.may_type(&alice).is_ok() && c.may_type(&owner).is_ok());
+        assert!(matches!(c.may_type(&bob), Err(Denied::Role(..))));
+        assert_eq!(c.lock(&alice, &req("acquire", None, false)).unwrap(), ["lock:alice"]);
+        assert!(c.may_type(&alice).is_ok());
+        assert!(matches!(c.may_type(&owner), Err(Denied::Locked(h)) if h == "alice"));
+        assert!(matches!(c.lock(&owner, &req("acquire", None, false)), Err(Denied::Locked(_))));
+        assert!(matches!(c.lock(&owner, &req("release", Some("carol"), false)), Err(Denied::Invalid(_))));
+        c.attached.insert("bob".into(), (Role::Viewer, 1));
+        assert!(matches!(c.lock(&alice, &req("release", Some("bob"), false)), Err(Denied::Role(Role::Viewer, _))));
+        assert!(matches!(c.lock(&alice, &req("release", Some("owner"), false)), Err(Denied::Invalid(_))));
+        assert_eq!(c.state().holder.as_deref(), Some("alice"));
+        assert_eq!(c.lock(&owner, &req("acquire", None, true)).unwrap(), ["lock:owner"]);
+        assert_eq!(c.lock(&owner, &req("release", None, false)).unwrap(), ["unlock:owner"]);
+        assert!(c.lock(&owner, &req("release", None, false)).is_err());
+        assert!(c.state().holder.is_none());
+    }
+
+    #[test]
+    fn requests_queue_until_the_holder_hands_over() {
+        let (mut c, owner, alice, _) = session();
+        c.lock(&alice, &req("acquire", None, false)).unwrap();
+        assert_eq!(c.lock(&owner, &req("request", None, false)).unwrap(), ["lock-request:owner"]);
+        c.lock(&owner, &req("request", None, false)).unwrap();
+        assert_eq!(c.state().requests, ["owner"]);
+        assert!(matches!(c.lock(&alice, &req("bogus", None, false)), Err(Denied::Invalid(_))));
+        c.attached.insert("owner".into(), (Role::Owner, 1));
+        assert_eq!(c.lock(&alice, &req("release", Some("owner"), false)).unwrap(), ["lock:owner"]);
+        assert_eq!(c.state().holder.as_deref(), Some("owner"));
+        assert!(c.state().requests.is_empty());
+        assert!(matches!(c.lock(&alice, &req("release", None, false)), Err(Denied::Invalid(_))));
+        // asking again while already holding it changes nothing
+        assert!(c.lock(&owner, &req("acquire", None, false)).unwrap().is_empty());
+    }
+}
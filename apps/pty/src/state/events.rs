@@ -0,0 +1,122 @@
+use crate::io::stream::FrameCursor;
+use crate::models::NodeEvent;
+use futures_util::stream::{self, Stream};
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+
+/// Events kept for `?from=` / Last-Event-ID replay.
+const RECENT_CAP: usize = 1024;
+
+/// Node-wide lifecycle feed behind GET /events.
+pub struct EventBus {
+    tx: broadcast::Sender<NodeEvent>,
+    recent: Mutex<VecDeque<NodeEvent>>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        let (tx, _rx) = broadcast::channel(RECENT_CAP);
+        Self { tx, recent: Mutex::new(VecDeque::with_capacity(RECENT_CAP)) }
+    }
+}
+
+impl EventBus {
+    /// Stamps `ev` with the next seq and the current time, and sends it.
+    pub fn publish(&self, mut ev: NodeEvent) {
+        let mut recent = self.recent.lock();
+        ev.seq = recent.back().map_or(0, |e| e.seq) + 1;
+        ev.ts_ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+        if recent.len() == RECENT_CAP { recent.pop_front(); }
+        recent.push_back(ev.clone());
+        let _ = self.tx.send(ev);
+    }
+
+    /// Events after `from`, then live ones; without `from` only live ones.
+    /// Events that dropped out of the replay buffer become one "gap" event.
+    pub fn feed(&self, from: Option<u64>) -> impl Stream<Item = NodeEvent> + Send + 'static {
+        let rx = self.tx.subscribe();
+        let mut pending = VecDeque::new();
+        let recent = self.recent.lock();
+        let mut last = recent.back().map_or(0, |e| e.seq);
+        if let Some(from) = from {
+            pending.extend(replay(&recent, from));
+            last = last.max(from);
+        }
+        drop(recent);
+        stream::unfold((rx, pending, last), |(mut rx, mut pending, mut last)| async move {
+            loop {
+                if let Some(ev) = pending.pop_front() {
+                    return Some((ev, (rx, pending, last)));
+                }
+                match rx.recv().await {
+                    Ok(ev) if ev.seq <= last => continue,
+                    Ok(ev) => {
+                        last = ev.seq;
+                        return Some((ev, (rx, pending, last)));
+                    }
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        pending.push_back(gap(last + 1, last + n));
+                        last += n;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        })
+    }
+}
+
+fn replay(recent: &VecDeque<NodeEvent>, from: u64) -> Vec<NodeEvent> {
+    let mut out = Vec::new();
+    if let Some(first) = recent.front().map(|e| e.seq) {
+        if first > from + 1 {
+            out.push(gap(from + 1, first - 1));
+        }
+    }
+    out.extend(recent.iter().filter(|e| e.seq > from).cloned());
+    out
+}
+
+fn gap(first: u64, last: u64) -> NodeEvent {
+    NodeEvent { reason: Some(format!("{first}-{last}")), ..NodeEvent::new("node", "", "gap") }
+}
+
+/// Follows one job's or session's frames and publishes what they say about
+/// its lifecycle: kills, resizes, close requests and the final exit.
+pub async fn watch(
+    bus: Arc<EventBus>,
+    template: NodeEvent,
+    mut cursor: FrameCursor,
+    status: impl Fn() -> (Option<i32>, Option<i32>),
+) {
+    let event = |name: &str| NodeEvent { event: name.into(), ..template.clone() };
+    let mut reason = None;
+    while let Some(f) = cursor.next().await {
+        if f.t != "event" {
+            continue;
+        }
+        let (name, arg) = f.d.split_once(':').unwrap_or((&f.d, ""));
+        match name {
+            "timeout" | "cancelled" => reason = Some(name.to_string()),
+            "killed" => bus.publish(NodeEvent { signal: Some(arg.into()), reason: reason.clone(), ..event("killed") }),
+            "close" => bus.publish(event("closed")),
+            "resize" => {
+                let (cols, rows) = arg.split_once('x').unwrap_or_default();
+                bus.publish(NodeEvent { cols: cols.parse().ok(), rows: rows.parse().ok(), ..event("resized") });
+            }
+            "exit" => {
+                let (code, signal) = status();
+                bus.publish(NodeEvent {
+                    exit_code: code,
+                    signal: signal.map(crate::executor::signal_name),
+                    reason: (arg == "oom").then(|| "oom".into()),
+                    ..event("exited")
+                });
+                return;
+            }
+            _ => {}
+        }
+    }
+}
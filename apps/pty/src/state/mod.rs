@@ -1,33 +1,138 @@
-use crate::models::StreamFrame;
+use crate::config::{LimitOverrides, SandboxConfig};
+use crate::auth::NodeAuth;
+use crate::files::SandboxRoot;
+use crate::executor::cgroup::{Cgroup, CgroupRoot};
+use crate::executor::isolation::Isolation;
+use crate::executor::namespaces::NsPlan;
+use crate::executor::seccomp;
+use crate::io::log::{FrameLog, LogStore};
+use crate::models::{JobSpec, StreamFrame};
 use parking_lot::Mutex;
 use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
-use std::sync::atomic::AtomicU64;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Instant, SystemTime};
 use tokio::io::unix::AsyncFd;
 use tokio::process::Child;
-use tokio::sync::{broadcast, RwLock};
+use tokio::sync::{broadcast, Notify, RwLock};
+
+pub mod collab;
+pub mod events;
+pub mod reaper;
 
 #[derive(Clone)]
 pub struct AppState {
     pub jobs: Arc<RwLock<HashMap<String, JobHandle>>>,
     pub sessions: Arc<RwLock<HashMap<String, SessionHandle>>>,
+    pub config: Arc<SandboxConfig>,
+    pub cgroups: Option<Arc<CgroupRoot>>,
+    pub namespaces: Option<Arc<NsPlan>>,
+    pub reaper: Arc<reaper::ReaperStats>,
+    pub files: Arc<SandboxRoot>,
+    pub auth: Arc<NodeAuth>,
+    pub logs: Option<Arc<LogStore>>,
+    pub events: Arc<events::EventBus>,
 }
 
 impl AppState {
-    pub fn new() -> Self {
+    pub fn new(config: SandboxConfig) -> Self {
+        let cgroups = if config.cgroup.enable {
+            match CgroupRoot::init(&config.cgroup) {
+                Ok(root) => Some(Arc::new(root)),
+                Err(e) => {
+                    tracing::warn!("cgroups disabled: {} ({e})", config.cgroup.root.display());
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        let namespaces = match NsPlan::new(&config.namespaces) {
+            Ok(plan) => Some(Arc::new(plan)),
+            Err(e) => {
+                tracing::warn!("namespaces disabled: {e}");
+                None
+            }
+        };
+        let logs = if config.log.enable {
+            match LogStore::open(&config.log) {
+                Ok(store) => Some(store),
+                Err(e) => {
+                    tracing::warn!("frame logs disabled: {} ({e})", config.log.dir.display());
+                    None
+                }
+            }
+        } else {
+            None
+        };
         Self {
             jobs: Arc::new(RwLock::new(HashMap::new())),
             sessions: Arc::new(RwLock::new(HashMap::new())),
+            files: Arc::new(SandboxRoot::new(&config.files.root)),
+            auth: Arc::new(NodeAuth::new(&config.auth)),
+            config: Arc::new(config),
+            cgroups,
+            namespaces,
+            logs,
+            events: Arc::new(events::EventBus::default()),
+            reaper: Arc::new(reaper::ReaperStats::default()),
         }
     }
+
+    /// A new frame log for `id`, or None when logging is off or fails.
+    pub fn frame_log(&self, id: &str) -> Option<Arc<FrameLog>> {
+        let store = self.logs.as_ref()?;
+        store.create(id).map_err(|e| tracing::warn!("frame log for {id}: {e}")).ok()
+    }
+
+    /// Builds the confinement for a new job or session: clamped limits, its
+    /// own cgroup when available, namespaces when requested, and a seccomp
+    /// profile (already validated against `seccomp::PROFILES`).
+    pub fn isolation(
+        &self,
+        id: &str,
+        limits: Option<&LimitOverrides>,
+        isolate: Option<bool>,
+        seccomp_profile: &str,
+    ) -> anyhow::Result<Isolation> {
+        let limits = self.config.limits.clamp(limits);
+        let cgroup = match &self.cgroups {
+            Some(root) => Some(Arc::new(root.create(id, &limits)?)),
+            None => None,
+        };
+        let namespaces = if isolate.unwrap_or(self.config.namespaces.enable) {
+            let Some(plan) = &self.namespaces else { anyhow::bail!("namespace isolation unavailable") };
+            Some(plan.clone())
+        } else {
+            None
+        };
+        let (seccomp, seccomp_listener) = match seccomp::Profile::named(seccomp_profile) {
+            Some(p) => {
+                let (child, listener) = seccomp::pair(Arc::new(p))?;
+                (Some(child), Some(listener))
+            }
+            None => (None, None),
+        };
+        Ok(Isolation { limits, cgroup, namespaces, seccomp, seccomp_listener })
+    }
 }
 
 pub struct JobHandle {
     pub latest_seq: Arc<AtomicU64>,
     pub tx: broadcast::Sender<StreamFrame>,
     pub exit_code: Arc<Mutex<Option<i32>>>,
+    pub exit_signal: Arc<Mutex<Option<i32>>>,
     pub backlog: Arc<Mutex<VecDeque<StreamFrame>>>,
     pub child: Arc<tokio::sync::Mutex<Child>>,
+    pub pid: i32,
+    pub cgroup: Option<Arc<Cgroup>>,
+    pub finished_at: Arc<Mutex<Option<Instant>>>,
+    pub cancel: Arc<Notify>,        // wakes the wait task to terminate the job
+    pub log: Option<Arc<FrameLog>>,
+    pub spec: Arc<JobSpec>,
+    pub started: Instant,           // zero point of frame timestamps
+    pub created_at: SystemTime,
+    pub bytes: Arc<AtomicU64>,      // output read from stdout and stderr
 }
 
 #[derive(Clone)]
@@ -35,10 +140,62 @@ pub struct SessionHandle {
     pub latest_seq: Arc<Mutex<u64>>,
     pub tx: broadcast::Sender<StreamFrame>,
     pub exit_code: Arc<Mutex<Option<i32>>>,
+    pub exit_signal: Arc<Mutex<Option<i32>>>,
     pub reader: Arc<AsyncFd<std::fs::File>>,
     pub writer: Arc<AsyncFd<std::fs::File>>,
     pub pid: i32,
     pub backlog: Arc<Mutex<VecDeque<StreamFrame>>>,
+    pub cgroup: Option<Arc<Cgroup>>,
+    pub finished_at: Arc<Mutex<Option<Instant>>>,
+    pub last_activity: Arc<Mutex<Instant>>,
+    pub hung_up: Arc<AtomicBool>,   // the reaper has sent SIGHUP; cleared by activity
+    pub idle_killed: Arc<AtomicBool>,  // the reaper has sent SIGKILL; never repeated
+    pub log: Option<Arc<FrameLog>>,
+    pub started: Instant,           // zero point of frame timestamps
+    pub created_at: SystemTime,
+    pub initial_size: (u16, u16),   // cols, rows; later sizes are in resize frames
+    pub shell: String,
+    pub screen: Arc<Mutex<vt100::Parser>>,  // fed all output; see `emit_output`
+    pub markers: bool,              // shell prints OSC 133 markers, so /run works
+    pub run_lock: Arc<tokio::sync::Mutex<()>>,  // one /run at a time
+    pub collab: Arc<Mutex<collab::Collab>>,     // attach tokens, presence, input lock
+    pub profile: String,
+    pub bytes: Arc<AtomicU64>,      // output read from the terminal
+}
+
+impl SessionHandle {
+    /// Records input or output, which also takes back a pending idle hang-up.
+    pub fn touch(&self) {
+        *self.last_activity.lock() = Instant::now();
+        self.hung_up.store(false, Ordering::Relaxed);
+    }
+
+    /// Numbers, logs, backlogs and broadcasts one frame.
+    pub fn emit(&self, t: &str, d: String, enc: Option<String>) {
+        self.touch();
+        let mut s = self.latest_seq.lock(); *s += 1;
+        let ts_ms = self.started.elapsed().as_millis() as u64;
+        let frame = StreamFrame { t: t.into(), seq: *s, d, enc, ts_ms };
+        if let Some(log) = &self.log { log.append(&frame); }
+        let mut b = self.backlog.lock();
+        if b.len() == b.capacity() { b.pop_front(); }
+        b.push_back(frame.clone());
+        let _ = self.tx.send(frame);
+    }
+
+    /// Feeds PTY output to the screen and emits it, under the screen lock so
+    /// a snapshot's seq always matches what it shows.
+    pub fn emit_output(&self, raw: &[u8], d: Option<String>, enc: Option<String>) {
+        let mut screen = self.screen.lock();
+        screen.process(raw);
+        if let Some(d) = d {
+            self.emit("stdout", d, enc);
+        }
+    }
+
+    pub fn push_event(&self, d: String) {
+        self.emit("event", d, None);
+    }
 }
 
 pub mod ids {
@@ -0,0 +1,102 @@
+use super::{AppState, SessionHandle};
+use crate::executor::pty;
+use crate::models::{NodeEvent, ReaperResponse};
+use nix::sys::signal::{killpg, Signal};
+use nix::unistd::Pid;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+#[derive(Default)]
+pub struct ReaperStats {
+    pub sweeps: AtomicU64,
+    pub sessions_hung_up: AtomicU64,
+    pub sessions_killed: AtomicU64,
+    pub sessions_evicted: AtomicU64,
+    pub jobs_evicted: AtomicU64,
+}
+
+impl ReaperStats {
+    pub fn snapshot(&self, jobs: usize, sessions: usize) -> ReaperResponse {
+        let get = |c: &AtomicU64| c.load(Ordering::Relaxed);
+        ReaperResponse {
+            sweeps: get(&self.sweeps),
+            sessions_hung_up: get(&self.sessions_hung_up),
+            sessions_killed: get(&self.sessions_killed),
+            sessions_evicted: get(&self.sessions_evicted),
+            jobs_evicted: get(&self.jobs_evicted),
+            jobs,
+            sessions,
+        }
+    }
+}
+
+/// Starts the background sweep, every `[timeouts].reap_interval_ms`.
+pub fn spawn(state: AppState) {
+    let every = state.config.timeouts.reap_interval_ms;
+    if every == 0 {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(Duration::from_millis(every));
+        loop {
+            tick.tick().await;
+            sweep(&state).await;
+        }
+    });
+}
+
+/// Evicts jobs and sessions that exited more than `retention_ms` ago, and
+/// hangs up sessions idle for `idle_session_ms` (SIGKILL if still there on
+/// the next sweep).
+pub async fn sweep(state: &AppState) {
+    let t = &state.config.timeouts;
+    let stats = &state.reaper;
+    let now = Instant::now();
+    let older_than = |at: Instant, ms: u64| ms > 0 && now.duration_since(at) >= Duration::from_millis(ms);
+
+    {
+        let mut jobs = state.jobs.write().await;
+        let before = jobs.len();
+        jobs.retain(|_, h| !h.finished_at.lock().is_some_and(|at| older_than(at, t.retention_ms)));
+        stats.jobs_evicted.fetch_add((before - jobs.len()) as u64, Ordering::Relaxed);
+    }
+
+    let mut sessions = state.sessions.write().await;
+    let before = sessions.len();
+    sessions.retain(|id, h| {
+        if let Some(at) = *h.finished_at.lock() {
+            return !older_than(at, t.retention_ms);
+        }
+        // once killed, an orphan may still hold the terminal open; leave it be
+        if !h.idle_killed.load(Ordering::Relaxed) && older_than(*h.last_activity.lock(), t.idle_session_ms) {
+            if h.hung_up.swap(true, Ordering::Relaxed) {
+                tracing::info!("reaper: killing idle session {id}");
+                kill_session(h);
+                h.idle_killed.store(true, Ordering::Relaxed);
+                stats.sessions_killed.fetch_add(1, Ordering::Relaxed);
+                state.events.publish(NodeEvent {
+                    signal: Some("SIGKILL".into()),
+                    reason: Some("idle".into()),
+                    ..NodeEvent::new("session", id, "killed")
+                });
+            } else {
+                tracing::info!("reaper: hanging up idle session {id}");
+                let _ = killpg(Pid::from_raw(h.pid), Signal::SIGHUP);
+                stats.sessions_hung_up.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        true
+    });
+    stats.sessions_evicted.fetch_add((before - sessions.len()) as u64, Ordering::Relaxed);
+    stats.sweeps.fetch_add(1, Ordering::Relaxed);
+}
+
+/// SIGKILLs everything the session started: its whole cgroup when it has one,
+/// otherwise the shell's process group and the terminal's foreground group.
+fn kill_session(h: &SessionHandle) {
+    if h.cgroup.as_ref().is_some_and(|cg| cg.kill().is_ok()) {
+        return;
+    }
+    let _ = killpg(Pid::from_raw(h.pid), Signal::SIGKILL);
+    let _ = pty::signal_pty(h, Signal::SIGKILL, true);
+}